@@ -0,0 +1,169 @@
+// Covariance and Pearson correlation between a client column `x` and a server column `y`
+// over the matched records. The sums Σxy, Σy, Σx, n, Σx² and Σy² are accumulated by the
+// passes below, combined across threads, and the join circuit only outputs the numerator
+// and the denominator of the statistic:
+//
+//     covariance:     n Σxy - Σx Σy    and    n²
+//     correlation:    n Σxy - Σx Σy    and    (n Σx² - (Σx)²)(n Σy² - (Σy)²)
+//
+// so that neither the sums nor the variances of the columns are revealed. The covariance
+// n⁻² (n Σxy - Σx Σy), or the correlation (n Σxy - Σx Σy) / sqrt(product of the variances),
+// is then computed in the clear and output in fixed point.
+use fancy_garbling::{
+    CrtBundle,
+    CrtGadgets,
+    Fancy,
+};
+
+use crate::{
+    aggregate::{Columns, MatchAggregate, PassSums},
+    output::FixedPoint,
+};
+
+/// Which of the two statistics is output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Moment {
+    /// The population covariance.
+    Covariance,
+    /// The Pearson correlation coefficient.
+    Correlation,
+}
+
+#[derive(Clone, Debug)]
+pub struct Correlation {
+    pub moment: Moment,
+    pub client_column: String,
+    pub server_column: String,
+    /// Largest values of the client and server columns.
+    pub max_client: u64,
+    pub max_server: u64,
+    /// Bound on the size of the intersection.
+    pub set_size: usize,
+    /// Number of decimal digits of the output.
+    pub digits: u32,
+}
+
+impl Correlation {
+    /// Check that the values computed by the join circuit fit in half the
+    /// modulus of `payload_size` bits, as they are read as signed values.
+    pub fn check(&self, payload_size: usize) -> Result<(), String> {
+        let limit = 1u128.checked_shl(payload_size as u32 - 1).unwrap_or(u128::MAX);
+        let n = self.set_size as u128;
+        let (x, y) = (self.max_client as u128, self.max_server as u128);
+        // The correlation multiplies the variances, of at most n² x² and n² y²
+        let mut moments = vec![x * y, x * x, y * y, 1];
+        if self.moment == Moment::Correlation {
+            moments.push((x * x).checked_mul(y * y)
+                                .and_then(|m| m.checked_mul(n * n))
+                                .ok_or("the moments overflow")?);
+        }
+        let max = moments.iter()
+                         .map(|m| m.checked_mul(n * n))
+                         .collect::<Option<Vec<u128>>>()
+                         .and_then(|m| m.into_iter().max())
+                         .ok_or("the moments overflow")?;
+        if max >= limit {
+            return Err(format!("the moments need {} bits, more than the {} bits available",
+                               128 - max.leading_zeros() + 1, payload_size));
+        }
+        Ok(())
+    }
+}
+
+impl MatchAggregate for Correlation {
+    type Output = FixedPoint;
+
+    fn name(&self) -> String {
+        match self.moment {
+            Moment::Covariance => "Covariance".to_owned(),
+            Moment::Correlation => "Correlation".to_owned(),
+        }
+    }
+
+    // Pass 0 accumulates (Σxy, Σy), pass 1 (Σx, n), pass 2 Σx² and pass 3 Σy².
+    fn npasses(&self) -> usize {
+        match self.moment {
+            Moment::Covariance => 2,
+            Moment::Correlation => 4,
+        }
+    }
+
+    fn client_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        let x = &columns[&self.client_column];
+        match pass {
+            0 | 1 => x.clone(),
+            2 => x.iter().map(|x| x * x).collect(),
+            _ => vec![1; x.len()],
+        }
+    }
+
+    fn server_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        let y = &columns[&self.server_column];
+        match pass {
+            0 => y.clone(),
+            3 => y.iter().map(|y| y * y).collect(),
+            _ => vec![1; y.len()],
+        }
+    }
+
+    fn count_pass(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn output<F: Fancy>(&self, f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>{
+        let (sum_xy, sum_y) = &sums[0];
+        let (sum_x, n) = &sums[1];
+
+        let n_xy = f.crt_mul(n, sum_xy)?;
+        let x_y = f.crt_mul(sum_x, sum_y)?;
+        let covariance = f.crt_sub(&n_xy, &x_y)?;
+        match self.moment {
+            Moment::Covariance => {
+                let n_n = f.crt_mul(n, n)?;
+                Ok(vec![covariance, n_n])
+            }
+            Moment::Correlation => {
+                let (sum_xx, _) = &sums[2];
+                let (sum_yy, _) = &sums[3];
+                let n_xx = f.crt_mul(n, sum_xx)?;
+                let x_x = f.crt_mul(sum_x, sum_x)?;
+                let variance_x = f.crt_sub(&n_xx, &x_x)?;
+                let n_yy = f.crt_mul(n, sum_yy)?;
+                let y_y = f.crt_mul(sum_y, sum_y)?;
+                let variance_y = f.crt_sub(&n_yy, &y_y)?;
+                let variances = f.crt_mul(&variance_x, &variance_y)?;
+                Ok(vec![covariance, variances])
+            }
+        }
+    }
+
+    // A matched pair changes n Σxy - Σx Σy by at most 2 N max(x) max(y), n² by 2 N + 1,
+    // and n Σx² - (Σx)², of at most N² max(x)², by at most 2 N max(x)², so that the
+    // product of the variances changes by at most 4 N³ max(x)² max(y)² + 4 N² max(x)² max(y)².
+    fn sensitivities(&self) -> Vec<f64> {
+        let n = self.set_size as f64;
+        let (x, y) = (self.max_client as f64, self.max_server as f64);
+        match self.moment {
+            Moment::Covariance => vec![2.0 * n * x * y, 2.0 * n + 1.0],
+            Moment::Correlation => vec![2.0 * n * x * y, 4.0 * (n * n * n + n * n) * x * x * y * y],
+        }
+    }
+
+    // The statistic is zero when it is undefined (no match, or a constant column).
+    // The noise may push the variances below zero: they are clamped.
+    fn finalize(&self, values: &[i128]) -> FixedPoint {
+        let covariance = values[0] as f64;
+        let statistic = match self.moment {
+            Moment::Covariance => {
+                let n_n = values[1].max(0) as f64;
+                if n_n > 0.0 { covariance / n_n } else { 0.0 }
+            }
+            Moment::Correlation => {
+                let variances = values[1].max(0) as f64;
+                if variances > 0.0 { (covariance / variances.sqrt()).max(-1.0).min(1.0) } else { 0.0 }
+            }
+        };
+        FixedPoint::from_f64(statistic, self.digits)
+    }
+}
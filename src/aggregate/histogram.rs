@@ -0,0 +1,143 @@
+// Histogram of a column of the matched records over the bucket boundaries agreed in the
+// configuration. Every bucket is counted by its own pass: the party owning the column
+// inputs the indicator of the bucket for each of its records and the other party inputs
+// ones, so that the `acc` of the pass counts the matched pairs falling into the bucket.
+// The indicators are only known to the owner of the column, which already knows in which
+// bucket each of its records falls: the other party learns neither the buckets nor which
+// records matched, and only the per-bucket counts are output by the join circuit.
+use fancy_garbling::{
+    CrtBundle,
+    Fancy,
+};
+use std::fmt;
+
+use crate::{
+    aggregate::{Columns, MatchAggregate, PassSums},
+    expression::Party,
+};
+
+/// The counts of the matched records in the buckets `[boundaries[i], boundaries[i + 1])`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Buckets {
+    pub boundaries: Vec<u64>,
+    pub counts: Vec<u128>,
+}
+
+impl fmt::Display for Buckets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let buckets = self.counts.iter()
+                                 .enumerate()
+                                 .map(|(i, count)| format!("[{}, {}): {}", self.boundaries[i], self.boundaries[i + 1], count))
+                                 .collect::<Vec<String>>();
+        write!(f, "{}", buckets.join(", "))
+    }
+}
+
+/// Histogram of the column `column` of `party`.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    pub party: Party,
+    pub column: String,
+    pub boundaries: Vec<u64>,
+}
+
+impl Histogram {
+    /// Parse the histogram column, written `c.<name>` or `s.<name>`, and its boundaries.
+    pub fn parse(column: &str, boundaries: &str) -> Histogram {
+        let (party, column) = Party::parse_column(column)
+                                    .unwrap_or_else(|| panic!("histogram column {} should start with c. or s.", column));
+        let boundaries = boundaries.split(',')
+                                   .map(|boundary| boundary.trim().parse::<u64>().unwrap())
+                                   .collect::<Vec<u64>>();
+        assert!(boundaries.len() >= 2, "a histogram needs at least two boundaries");
+        assert!(boundaries.windows(2).all(|w| w[0] < w[1]), "the histogram boundaries should be increasing");
+
+        Histogram{
+            party,
+            column,
+            boundaries,
+        }
+    }
+
+    // Input of `party` for the pass counting `bucket`. The other party does not have the
+    // column, and inputs ones for each of its records.
+    fn column(&self, party: Party, bucket: usize, columns: &Columns) -> Vec<u64> {
+        if party != self.party {
+            return vec![1; columns["payload"].len()];
+        }
+        let values = &columns[&self.column];
+        let (low, high) = (self.boundaries[bucket], self.boundaries[bucket + 1]);
+        values.iter().map(|x| (low <= *x && *x < high) as u64).collect()
+    }
+}
+
+impl MatchAggregate for Histogram {
+    type Output = Buckets;
+
+    fn name(&self) -> String {
+        "Histogram".to_owned()
+    }
+
+    fn npasses(&self) -> usize {
+        self.boundaries.len() - 1
+    }
+
+    fn client_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        self.column(Party::Client, pass, columns)
+    }
+
+    fn server_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        self.column(Party::Server, pass, columns)
+    }
+
+    // The server inputs ones when the client owns the column
+    fn count_pass(&self) -> Option<usize> {
+        if self.party == Party::Client { Some(0) } else { None }
+    }
+
+    fn output<F: Fancy>(&self, _f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>{
+        Ok(sums.iter().map(|(acc, _)| acc.clone()).collect())
+    }
+
+    fn sensitivities(&self) -> Vec<f64> {
+        vec![1.0; self.npasses()]
+    }
+
+    // The buckets are disjoint: a matched pair only changes the count of its own bucket.
+    fn ncompositions(&self) -> usize {
+        1
+    }
+
+    fn finalize(&self, values: &[i128]) -> Buckets {
+        Buckets{
+            boundaries: self.boundaries.clone(),
+            counts: values.iter().map(|count| (*count).max(0) as u128).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(entries: &[(&str, Vec<u64>)]) -> Columns {
+        entries.iter().map(|(name, values)| (name.to_string(), values.clone())).collect()
+    }
+
+    #[test]
+    fn owner_inputs_the_indicators_of_the_buckets() {
+        let histogram = Histogram::parse("c.age", "0, 18, 65");
+        let client = columns(&[("payload", vec![5, 5, 5]), ("age", vec![10, 30, 70])]);
+        assert_eq!(histogram.client_column(0, &client), vec![1, 0, 0]);
+        assert_eq!(histogram.client_column(1, &client), vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn other_party_inputs_ones_without_the_column() {
+        let histogram = Histogram::parse("c.age", "0, 18, 65");
+        let server = columns(&[("payload", vec![1, 2, 3, 4])]);
+        assert_eq!(histogram.server_column(0, &server), vec![1; 4]);
+        assert_eq!(histogram.server_column(1, &server), vec![1; 4]);
+    }
+}
@@ -0,0 +1,112 @@
+// Labeled PSI: the client retrieves the value of a server column for each of its records
+// that matched, and the server learns nothing. The values are packed in base
+// `radix = max + 2`, shifted by one so that a zero digit marks a record that did not match.
+// Pass j covers the client records j * width, ..., (j + 1) * width - 1: the client inputs
+// radix^(i - j * width) for its record i of that range and 0 for the others, and the server
+// inputs its value plus one. Every record falls in a single bin, so that the digits of the
+// acc of the pass are the labels of the records of the range.
+//
+// As for the membership, the records keep their position in the csv through `pad_data`,
+// and the position of a record is carried by its payload rather than by its bin.
+use fancy_garbling::{
+    CrtBundle,
+    Fancy,
+};
+use std::fmt;
+
+use crate::aggregate::{Columns, MatchAggregate, PassSums};
+
+/// The server value of every matched client record, by index among the data rows of the client csv.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Labels(pub Vec<(usize, u64)>);
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let labels = self.0.iter().map(|(row, value)| format!("{}: {}", row, value)).collect::<Vec<String>>();
+        write!(f, "{}", labels.join(", "))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lookup {
+    /// The server column that is retrieved.
+    pub column: String,
+    /// Bound on the number of client records.
+    pub set_size: usize,
+    /// Base the labels are packed in.
+    pub radix: u128,
+    /// Number of records per pass.
+    pub width: usize,
+}
+
+impl Lookup {
+    /// The packed labels are read as signed values modulo a modulus of `payload_size` bits,
+    /// and the client payloads are 64 bits wide.
+    pub fn new(column: String, max: u64, set_size: usize, payload_size: usize) -> Lookup {
+        let radix = max as u128 + 2;
+        let mut width = 0;
+        while width < 64 && radix.checked_pow(width as u32 + 1).map_or(false, |x| x <= 1 << (payload_size - 1).min(64)) {
+            width = width + 1;
+        }
+        assert!(width > 0, "the values of {} do not fit the payload_size", column);
+        Lookup{
+            column,
+            set_size,
+            radix,
+            width,
+        }
+    }
+}
+
+impl MatchAggregate for Lookup {
+    type Output = Labels;
+
+    fn name(&self) -> String {
+        format!("Lookup of s.{}", self.column)
+    }
+
+    fn npasses(&self) -> usize {
+        ((self.set_size + self.width - 1) / self.width).max(1)
+    }
+
+    fn client_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        let offset = pass * self.width;
+        (0..columns["payload"].len())
+            .map(|i| if offset <= i && i < offset + self.width { self.radix.pow((i - offset) as u32) as u64 } else { 0 })
+            .collect()
+    }
+
+    fn server_column(&self, _pass: usize, columns: &Columns) -> Vec<u64> {
+        columns[&self.column].iter().map(|x| x + 1).collect()
+    }
+
+    fn output<F: Fancy>(&self, _f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>{
+        Ok(sums.iter().map(|(acc, _)| acc.clone()).collect())
+    }
+
+    fn sensitivities(&self) -> Vec<f64> {
+        panic!("noise cannot be added to the labels");
+    }
+
+    fn finalize(&self, values: &[i128]) -> Labels {
+        let mut labels = Vec::new();
+        for (pass, packed) in values.iter().enumerate() {
+            let mut packed = *packed as u128;
+            for digit in 0..self.width {
+                let label = packed % self.radix;
+                if label > 0 {
+                    labels.push((pass * self.width + digit, (label - 1) as u64));
+                }
+                packed = packed / self.radix;
+            }
+        }
+        Labels(labels)
+    }
+
+    fn csv(&self, labels: &Labels) -> Option<Vec<String>> {
+        let mut lines = vec![format!("row, {}", self.column)];
+        lines.extend(labels.0.iter().map(|(row, value)| format!("{}, {}", row, value)));
+        Some(lines)
+    }
+}
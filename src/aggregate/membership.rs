@@ -0,0 +1,101 @@
+// Intersection membership of the client records: the client learns which of its records
+// matched, and the server learns nothing. Pass j covers the client records
+// j * width, ..., (j + 1) * width - 1: the client inputs 2^(i - j * width) for its record i
+// of that range and 0 for the others, and the server inputs ones. Every record falls in a
+// single bin, so that the acc of the pass is the bitmap of the matched records of the range.
+//
+// The records keep their position in the csv through the computation: `pad_data` appends
+// the dummy records after the real ones, with a zero payload that never contributes, and
+// the position of a record within the bitmaps is carried by its payload rather than by the
+// bin it is hashed to.
+use fancy_garbling::{
+    CrtBundle,
+    Fancy,
+};
+use std::fmt;
+
+use crate::aggregate::{Columns, MatchAggregate, PassSums};
+
+/// The indices of the matched records among the data rows of the client csv.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchedRows(pub Vec<usize>);
+
+impl fmt::Display for MatchedRows {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self.0.iter().map(|row| row.to_string()).collect::<Vec<String>>();
+        write!(f, "{}", rows.join(", "))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Membership {
+    /// Bound on the number of client records.
+    pub set_size: usize,
+    /// Number of records per pass.
+    pub width: usize,
+}
+
+impl Membership {
+    /// The bitmaps are read as signed values modulo a modulus of `payload_size` bits,
+    /// and the client payloads are 64 bits wide.
+    pub fn new(set_size: usize, payload_size: usize) -> Membership {
+        Membership{
+            set_size,
+            width: (payload_size - 1).min(63),
+        }
+    }
+}
+
+impl MatchAggregate for Membership {
+    type Output = MatchedRows;
+
+    fn name(&self) -> String {
+        "Matched rows".to_owned()
+    }
+
+    fn npasses(&self) -> usize {
+        ((self.set_size + self.width - 1) / self.width).max(1)
+    }
+
+    fn client_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        let offset = pass * self.width;
+        (0..columns["payload"].len())
+            .map(|i| if offset <= i && i < offset + self.width { 1 << (i - offset) } else { 0 })
+            .collect()
+    }
+
+    fn server_column(&self, _pass: usize, columns: &Columns) -> Vec<u64> {
+        vec![1; columns["payload"].len()]
+    }
+
+    fn count_pass(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn output<F: Fancy>(&self, _f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>{
+        Ok(sums.iter().map(|(acc, _)| acc.clone()).collect())
+    }
+
+    fn sensitivities(&self) -> Vec<f64> {
+        panic!("noise cannot be added to the matched rows");
+    }
+
+    fn finalize(&self, values: &[i128]) -> MatchedRows {
+        let mut rows = Vec::new();
+        for (pass, bitmap) in values.iter().enumerate() {
+            for bit in 0..self.width {
+                if (bitmap >> bit) & 1 == 1 {
+                    rows.push(pass * self.width + bit);
+                }
+            }
+        }
+        MatchedRows(rows)
+    }
+
+    fn csv(&self, rows: &MatchedRows) -> Option<Vec<String>> {
+        let mut lines = vec!["row".to_owned()];
+        lines.extend(rows.0.iter().map(|row| row.to_string()));
+        Some(lines)
+    }
+}
@@ -0,0 +1,198 @@
+// Statistics computed over the matched pairs of the intersection.
+//
+// The per-thread circuit accumulates, for every matched pair of client payload `c` and
+// server payload `s`, the sums `acc += c * s` and `sum_weights += s`. A statistic defines
+// its per-match accumulation by choosing the columns fed into that gadget: it runs one or
+// more passes, each with a client column and a server column computed from the parties'
+// payloads. The partial sums of every pass are then combined across batches, added up
+// unless the statistic has its own gadget, and turned into the bundles that are output by
+// the join circuit, before being decoded in the clear.
+//
+// The transport and orchestration code is generic over `MatchAggregate`, so that new
+// statistics can be added by implementing this trait alone. The `statistic` entry of the
+// configuration selects the one that is computed.
+pub mod correlation;
+pub mod histogram;
+pub mod lookup;
+pub mod membership;
+pub mod overlap;
+pub mod quantile;
+pub mod regression;
+
+use fancy_garbling::{
+    CrtBundle,
+    Fancy,
+    Wire,
+};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+};
+
+use crate::circuit;
+
+/// The statistics that can be selected in the configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statistic {
+    /// The `aggregate` expression.
+    Expression,
+    /// The histogram of `histogram_column` over `histogram_boundaries`.
+    Histogram,
+    /// The covariance of `correlation_client_column` and `correlation_server_column`.
+    Covariance,
+    /// The correlation of `correlation_client_column` and `correlation_server_column`.
+    Correlation,
+    /// The regression of `regression_outcome` on `regression_covariates`.
+    Regression,
+    /// The `quantile` of `quantile_column`.
+    Quantile,
+    /// The client records that matched.
+    Membership,
+    /// The `lookup_column` of the server for the client records that matched.
+    Lookup,
+    /// The sizes of the intersection and of the union, and the Jaccard index of the sets.
+    Jaccard,
+}
+
+impl Statistic {
+    pub fn parse(statistic: &str) -> Statistic {
+        match statistic {
+            "expression" => Statistic::Expression,
+            "histogram" => Statistic::Histogram,
+            "covariance" => Statistic::Covariance,
+            "correlation" => Statistic::Correlation,
+            "regression" => Statistic::Regression,
+            "quantile" => Statistic::Quantile,
+            "membership" => Statistic::Membership,
+            "lookup" => Statistic::Lookup,
+            "jaccard" => Statistic::Jaccard,
+            _ => panic!("unknown statistic {}, expected expression, histogram, covariance, correlation, \
+                        regression, quantile, membership, lookup or jaccard", statistic),
+        }
+    }
+}
+
+/// The columns of a party, by name. The payload column is always named `payload`.
+pub type Columns = HashMap<String, Vec<u64>>;
+
+/// A column declared in `client_columns` or `server_columns` as `name:position:bits`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub position: usize,
+    pub bits: u32,
+}
+
+impl ColumnSpec {
+    /// Parse a comma separated list of column declarations, `none` declaring no column.
+    pub fn parse_list(specs: &str) -> Vec<ColumnSpec> {
+        if specs == "none" {
+            return Vec::new();
+        }
+        specs.split(',')
+             .map(|spec|{
+                let spec_split = spec.trim().split(':').collect::<Vec<&str>>();
+                assert!(spec_split.len() == 3, "column {} is not declared as name:position:bits", spec);
+                ColumnSpec{
+                    name: spec_split[0].to_owned(),
+                    position: spec_split[1].parse::<usize>().unwrap(),
+                    bits: spec_split[2].parse::<u32>().unwrap(),
+                }
+             }).collect()
+    }
+
+    /// The largest value of the column.
+    pub fn max(&self) -> u64 {
+        1u64.checked_shl(self.bits).map(|x| x - 1).unwrap_or(u64::MAX)
+    }
+}
+
+/// The largest value of every column of a party: the payloads are below `max_payload`.
+pub fn column_bounds(specs: &[ColumnSpec], max_payload: u64) -> HashMap<String, u64> {
+    let mut bounds = HashMap::new();
+    bounds.insert("payload".to_owned(), max_payload - 1);
+    for spec in specs {
+        bounds.insert(spec.name.clone(), spec.max());
+    }
+    bounds
+}
+
+/// The summed `(acc, sum_weights)` of a pass, over every megabin.
+pub type PassSums<W> = (CrtBundle<W>, CrtBundle<W>);
+
+pub trait MatchAggregate: Clone {
+    /// The statistic once decoded in the clear.
+    type Output: Display;
+
+    /// Name of the statistic, used when reporting the results.
+    fn name(&self) -> String;
+
+    /// Number of passes of the per-thread computation the statistic needs.
+    fn npasses(&self) -> usize;
+
+    /// Client column of `pass`, computed from the client columns.
+    fn client_column(&self, pass: usize, columns: &Columns) -> Vec<u64>;
+
+    /// Server column of `pass`, computed from the server columns.
+    fn server_column(&self, pass: usize, columns: &Columns) -> Vec<u64>;
+
+    /// Combine the partial `(acc, sum_weights)` of a pass computed by two threads. The partial
+    /// results are combined before the join circuit, by each party on the wires it holds, so
+    /// only the free gates may be used. They are added by default.
+    fn combine(x: &PassSums<Wire>, y: &PassSums<Wire>) -> PassSums<Wire> {
+        circuit::add_sums(x, y)
+    }
+
+    /// Compute the bundles to output from the combined `(acc, sum_weights)` of every pass.
+    fn output<F: Fancy>(&self, f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>;
+
+    /// Sensitivity of every output bundle, used to calibrate the differentially private noise.
+    fn sensitivities(&self) -> Vec<f64>;
+
+    /// Number of rounds of passes and joins the statistic needs. The passes of a round
+    /// may depend on the outputs of the previous rounds.
+    fn nrounds(&self) -> usize {
+        1
+    }
+
+    /// Record the outputs of a round before the last, which are revealed to both parties.
+    fn next_round(&mut self, _values: &[i128]) {}
+
+    /// Whether `pass` is computed again in the rounds after the first. The passes that do
+    /// not depend on the outputs of the previous rounds are only computed in the first round,
+    /// and their partial results are joined in every round.
+    fn recompute(&self, _pass: usize) -> bool {
+        true
+    }
+
+    /// A pass whose server column is one for every record, if the statistic has one. Its
+    /// `sum_weights` counts the matched records, and stands in for the additional pass that
+    /// counts them when a minimum intersection size is required.
+    fn count_pass(&self) -> Option<usize> {
+        None
+    }
+
+    /// Whether the statistic depends on the sizes of the parties' sets, which are then
+    /// revealed to each other before the last join.
+    fn reveals_set_sizes(&self) -> bool {
+        false
+    }
+
+    /// Record the sizes of the client set, padding excluded, and of the server set.
+    fn set_sizes(&mut self, _client_size: usize, _server_size: usize) {}
+
+    /// Number of outputs the privacy budget is split between.
+    fn ncompositions(&self) -> usize {
+        self.sensitivities().len()
+    }
+
+    /// Decode the statistic from the output values, read as signed integers.
+    fn finalize(&self, values: &[i128]) -> Self::Output;
+
+    /// Lines of the csv the statistic is also written to, header first, for the statistics
+    /// that are tables of records rather than values.
+    fn csv(&self, _output: &Self::Output) -> Option<Vec<String>> {
+        None
+    }
+}
@@ -0,0 +1,108 @@
+// Overlap of the parties' sets: the size of the intersection, the size of the union and
+// their ratio, the Jaccard index. The intersection is counted by a single pass with unit
+// columns, and the union follows from the sizes of the sets, which both parties reveal to
+// each other in the clear: |A ∪ B| = |A| + |B| - |A ∩ B|.
+//
+// The client pads its set with `client_padding` dummy records before the bucketization, so
+// the size the server observes then is not the size of the client set. The client reveals
+// the number of its real records instead, and the dummies input a zero column so that they
+// are never counted in the intersection.
+use fancy_garbling::{
+    CrtBundle,
+    Fancy,
+};
+use std::fmt;
+
+use crate::{
+    aggregate::{Columns, MatchAggregate, PassSums},
+    output::FixedPoint,
+};
+
+/// The overlap of the sets once decoded in the clear.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlap {
+    pub intersection: i128,
+    pub union: i128,
+    pub jaccard: FixedPoint,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "intersection {}, union {}, jaccard {}", self.intersection, self.union, self.jaccard)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Jaccard {
+    /// Number of records of the client, padding excluded, once revealed.
+    pub client_size: usize,
+    /// Number of records of the server in the category, once revealed.
+    pub server_size: usize,
+    /// Number of decimal digits of the Jaccard index.
+    pub digits: u32,
+}
+
+impl Jaccard {
+    pub fn new(digits: u32) -> Jaccard {
+        Jaccard{
+            client_size: 0,
+            server_size: 0,
+            digits,
+        }
+    }
+}
+
+impl MatchAggregate for Jaccard {
+    type Output = Overlap;
+
+    fn name(&self) -> String {
+        "Overlap".to_owned()
+    }
+
+    fn npasses(&self) -> usize {
+        1
+    }
+
+    fn client_column(&self, _pass: usize, columns: &Columns) -> Vec<u64> {
+        vec![1; columns["payload"].len()]
+    }
+
+    fn server_column(&self, _pass: usize, columns: &Columns) -> Vec<u64> {
+        vec![1; columns["payload"].len()]
+    }
+
+    fn count_pass(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn output<F: Fancy>(&self, _f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>{
+        let (acc, _) = &sums[0];
+        Ok(vec![acc.clone()])
+    }
+
+    fn sensitivities(&self) -> Vec<f64> {
+        vec![1.0]
+    }
+
+    fn reveals_set_sizes(&self) -> bool {
+        true
+    }
+
+    fn set_sizes(&mut self, client_size: usize, server_size: usize) {
+        self.client_size = client_size;
+        self.server_size = server_size;
+    }
+
+    /// The noisy intersection is clamped to the sizes of the sets.
+    fn finalize(&self, values: &[i128]) -> Overlap {
+        let intersection = values[0].max(0).min(self.client_size.min(self.server_size) as i128);
+        let union = (self.client_size + self.server_size) as i128 - intersection;
+        let jaccard = if union > 0 { intersection as f64 / union as f64 } else { 0.0 };
+        Overlap{
+            intersection,
+            union,
+            jaccard: FixedPoint::from_f64(jaccard, self.digits),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// Quantile of a column of the matched records, found by a binary search over the domain
+// of the column. Every round counts the matched records whose value is at most the current
+// threshold t: the owner of the column inputs the indicator [x <= t] and the other party
+// ones. A second pass counts the matched records n: it does not depend on the threshold,
+// so it is only computed in the first round and joined in every round. The join circuit compares
+// count * denominator >= n * numerator and outputs a single bit, telling both parties
+// which half of the domain the quantile lies in.
+//
+// The quantile is the smallest value v such that at least a fraction `quantile` of the
+// matched records are at most v. The bits output by the rounds are exactly the path of
+// the binary search to that value: they reveal nothing but the final quantile, which is
+// therefore learnt by both parties.
+use fancy_garbling::{
+    CrtBundle,
+    CrtGadgets,
+    Fancy,
+};
+
+use crate::{
+    aggregate::{Columns, MatchAggregate, PassSums},
+    circuit,
+    expression::Party,
+};
+
+/// Quantile of the column `column` of `party`, whose values are at most `max`.
+#[derive(Clone, Debug)]
+pub struct Quantile {
+    pub party: Party,
+    pub column: String,
+    /// The quantile, as the fraction numerator / denominator.
+    pub numerator: u64,
+    pub denominator: u64,
+    pub max: u64,
+    // The range of the binary search, which contains the quantile
+    low: u64,
+    high: u64,
+}
+
+impl Quantile {
+    /// The quantile is written as a decimal between 0 and 1, such as 0.5 for the median.
+    pub fn new(party: Party, column: String, quantile: &str, max: u64) -> Quantile {
+        let (integer, fraction) = match quantile.find('.') {
+            Some(i) => (&quantile[..i], &quantile[i + 1..]),
+            None => (quantile, ""),
+        };
+        let denominator = 10u64.pow(fraction.len() as u32);
+        let numerator = format!("{}{}", integer, fraction).parse::<u64>()
+                            .unwrap_or_else(|_| panic!("invalid quantile {}", quantile));
+        assert!(numerator <= denominator, "the quantile {} should be between 0 and 1", quantile);
+
+        Quantile{
+            party,
+            column,
+            numerator,
+            denominator,
+            max,
+            low: 0,
+            high: max,
+        }
+    }
+
+    /// Check that the compared values fit in half the modulus of `payload_size` bits.
+    pub fn check(&self, set_size: usize, payload_size: usize) -> Result<(), String> {
+        let limit = 1u128.checked_shl(payload_size as u32 - 1).unwrap_or(u128::MAX);
+        if (self.denominator as u128) * (set_size as u128) >= limit {
+            return Err(format!("the quantile needs more than the {} bits available", payload_size));
+        }
+        Ok(())
+    }
+
+    fn threshold(&self) -> u64 {
+        self.low + (self.high - self.low) / 2
+    }
+
+    // Input of `party` for `pass`. The other party does not have the column, and inputs
+    // ones for each of its records.
+    fn column(&self, party: Party, pass: usize, columns: &Columns) -> Vec<u64> {
+        if party != self.party || pass == 1 {
+            return vec![1; columns["payload"].len()];
+        }
+        let values = &columns[&self.column];
+        let threshold = self.threshold();
+        values.iter().map(|x| (*x <= threshold) as u64).collect()
+    }
+}
+
+impl MatchAggregate for Quantile {
+    type Output = u64;
+
+    fn name(&self) -> String {
+        "Quantile".to_owned()
+    }
+
+    // Every round halves the range of the search, the last one settling between two values.
+    fn nrounds(&self) -> usize {
+        ((64 - self.max.leading_zeros()) as usize).max(1)
+    }
+
+    fn next_round(&mut self, values: &[i128]) {
+        let threshold = self.threshold();
+        if values[0] == 1 {
+            self.high = threshold;
+        }else{
+            self.low = (threshold + 1).min(self.high);
+        }
+    }
+
+    fn npasses(&self) -> usize {
+        2
+    }
+
+    fn recompute(&self, pass: usize) -> bool {
+        pass == 0
+    }
+
+    fn client_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        self.column(Party::Client, pass, columns)
+    }
+
+    fn server_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        self.column(Party::Server, pass, columns)
+    }
+
+    fn count_pass(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn output<F: Fancy>(&self, f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>{
+        let (count, _) = &sums[0];
+        let (n, _) = &sums[1];
+        let q = n.composite_modulus();
+
+        let count = f.crt_cmul(count, self.denominator as u128)?;
+        let n = f.crt_cmul(n, self.numerator as u128)?;
+        let below = f.crt_geq(&count, &n, "100%")?;
+        let one = f.crt_constant_bundle(1, q)?;
+        Ok(vec![circuit::gate(f, &below, &one)?])
+    }
+
+    fn sensitivities(&self) -> Vec<f64> {
+        panic!("noise cannot be added to the quantile");
+    }
+
+    fn finalize(&self, values: &[i128]) -> u64 {
+        let threshold = self.threshold();
+        if values[0] == 1 {
+            threshold
+        }else{
+            (threshold + 1).min(self.high)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(entries: &[(&str, Vec<u64>)]) -> Columns {
+        entries.iter().map(|(name, values)| (name.to_string(), values.clone())).collect()
+    }
+
+    #[test]
+    fn owner_inputs_the_indicators_of_the_threshold() {
+        let quantile = Quantile::new(Party::Server, "income".to_owned(), "0.5", 100);
+        let server = columns(&[("payload", vec![1, 1, 1]), ("income", vec![10, 50, 90])]);
+        assert_eq!(quantile.server_column(0, &server), vec![1, 1, 0]);
+        assert_eq!(quantile.server_column(1, &server), vec![1; 3]);
+    }
+
+    #[test]
+    fn other_party_inputs_ones_without_the_column() {
+        let quantile = Quantile::new(Party::Server, "income".to_owned(), "0.5", 100);
+        let client = columns(&[("payload", vec![7, 8])]);
+        assert_eq!(quantile.client_column(0, &client), vec![1; 2]);
+        assert_eq!(quantile.client_column(1, &client), vec![1; 2]);
+    }
+}
@@ -0,0 +1,323 @@
+// Ordinary least squares of a client column `y` on server columns x_1, ..., x_k and an
+// intercept, over the matched records. With the features f_0 = 1, f_1 = x_1, ..., f_k = x_k,
+// the sufficient statistics are XᵀX[i][j] = Σ f_i f_j and Xᵀy[i] = Σ f_i y. They are
+// accumulated by the passes below: pass i (for i = 0..=k) has the client input y and the
+// server input f_i, so that its acc is Xᵀy[i] and its sum of weights XᵀX[0][i]; the
+// remaining passes have the client input 1 and the server input f_i f_j for 1 <= i <= j.
+//
+// With `regression_reveal: determinants`, the join circuit solves the normal equations by
+// Cramer's rule, β_i = det(A_i) / det(XᵀX) where A_i is XᵀX with its i-th column replaced
+// by Xᵀy, and outputs the determinants, which are divided in the clear as the circuit has no
+// division. This mode does not reveal the coefficients only: both parties learn det(XᵀX) and
+// every det(A_i), that is the coefficients along with their common denominator.
+// With `regression_reveal: statistics`, XᵀX and Xᵀy are revealed instead and the
+// coefficients are computed from them in the clear; this is the only mode in which
+// differentially private noise can be added, the coefficients being post-processed.
+use fancy_garbling::{
+    CrtBundle,
+    CrtGadgets,
+    Fancy,
+};
+use std::fmt;
+
+use crate::{
+    aggregate::{Columns, MatchAggregate, PassSums},
+    output::FixedPoint,
+};
+
+// The determinants are expanded along their first row, which takes (k + 1)! products.
+pub const MAX_COVARIATES: usize = 3;
+
+/// What the regression reveals.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reveal {
+    /// The determinants of Cramer's rule, from which the coefficients are computed.
+    Determinants,
+    /// The sufficient statistics XᵀX and Xᵀy.
+    Statistics,
+}
+
+impl Reveal {
+    pub fn parse(reveal: &str) -> Reveal {
+        match reveal {
+            "determinants" => Reveal::Determinants,
+            "statistics" => Reveal::Statistics,
+            _ => panic!("unknown regression_reveal {}, expected determinants or statistics", reveal),
+        }
+    }
+}
+
+/// The fitted coefficients, the intercept first, and the sufficient statistics if revealed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fit {
+    pub names: Vec<String>,
+    pub coefficients: Vec<FixedPoint>,
+    pub statistics: Option<(Vec<Vec<i128>>, Vec<i128>)>,
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let coefficients = self.names.iter()
+                                     .zip(self.coefficients.iter())
+                                     .map(|(name, coefficient)| format!("{}: {}", name, coefficient))
+                                     .collect::<Vec<String>>();
+        write!(f, "{}", coefficients.join(", "))?;
+        if let Some((xtx, xty)) = &self.statistics {
+            write!(f, " (XtX: {:?}, Xty: {:?})", xtx, xty)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Regression {
+    pub outcome: String,
+    pub covariates: Vec<String>,
+    pub reveal: Reveal,
+    /// Largest values of the outcome and of every covariate.
+    pub max_outcome: u64,
+    pub max_covariates: Vec<u64>,
+    /// Bound on the size of the intersection.
+    pub set_size: usize,
+    /// Number of decimal digits of the coefficients.
+    pub digits: u32,
+}
+
+impl Regression {
+    // Number of features, the intercept included.
+    fn nfeatures(&self) -> usize {
+        self.covariates.len() + 1
+    }
+
+    // The pairs i <= j of features whose products are accumulated by the passes after the first k + 1.
+    fn pairs(&self) -> Vec<(usize, usize)> {
+        let m = self.nfeatures();
+        (1..m).flat_map(|i| (i..m).map(move |j| (i, j))).collect()
+    }
+
+    // Largest value of every feature.
+    fn max_features(&self) -> Vec<f64> {
+        let mut max = vec![1.0];
+        max.extend(self.max_covariates.iter().map(|x| *x as f64));
+        max
+    }
+
+    /// Check that the values output by the join circuit fit in half the modulus of
+    /// `payload_size` bits. The determinants are bounded by Hadamard's inequality.
+    pub fn check(&self, payload_size: usize) -> Result<(), String> {
+        if self.covariates.is_empty() || self.covariates.len() > MAX_COVARIATES {
+            return Err(format!("the regression takes between 1 and {} covariates", MAX_COVARIATES));
+        }
+        let limit = 2f64.powi(payload_size as i32 - 1);
+        let n = self.set_size as f64;
+        let max = self.max_features();
+        let max_y = self.max_outcome as f64;
+
+        let bound = match self.reveal {
+            Reveal::Statistics => max.iter()
+                                     .flat_map(|x| max.iter().map(move |z| n * x * z))
+                                     .chain(max.iter().map(|x| n * x * max_y))
+                                     .fold(0.0, f64::max),
+            // Every row of A_i is bounded by the row of XᵀX extended with Xᵀy
+            Reveal::Determinants => max.iter()
+                                       .map(|x| {
+                                           let row = max.iter().map(|z| (n * x * z).powi(2)).sum::<f64>();
+                                           (row + (n * x * max_y).powi(2)).sqrt()
+                                       })
+                                       .product(),
+        };
+        if bound >= limit {
+            return Err(format!("the regression needs {} bits, more than the {} bits available",
+                               bound.log2().ceil() as usize + 1, payload_size));
+        }
+        Ok(())
+    }
+
+    // Solve XᵀX β = Xᵀy in the clear by Gaussian elimination with partial pivoting.
+    // The coefficients are zero when the system is singular.
+    fn solve(xtx: &[Vec<i128>], xty: &[i128]) -> Vec<f64> {
+        let m = xty.len();
+        let mut a = xtx.iter()
+                       .zip(xty.iter())
+                       .map(|(row, y)| {
+                           let mut row = row.iter().map(|x| *x as f64).collect::<Vec<f64>>();
+                           row.push(*y as f64);
+                           row
+                       })
+                       .collect::<Vec<Vec<f64>>>();
+        for i in 0..m {
+            let pivot = (i..m).max_by(|r, s| a[*r][i].abs().partial_cmp(&a[*s][i].abs()).unwrap()).unwrap();
+            if a[pivot][i] == 0.0 {
+                return vec![0.0; m];
+            }
+            a.swap(i, pivot);
+            for r in 0..m {
+                if r != i {
+                    let factor = a[r][i] / a[i][i];
+                    for c in i..=m {
+                        a[r][c] -= factor * a[i][c];
+                    }
+                }
+            }
+        }
+        (0..m).map(|i| a[i][m] / a[i][i]).collect()
+    }
+}
+
+impl MatchAggregate for Regression {
+    type Output = Fit;
+
+    fn name(&self) -> String {
+        "Regression".to_owned()
+    }
+
+    fn npasses(&self) -> usize {
+        self.nfeatures() + self.pairs().len()
+    }
+
+    fn client_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        let y = &columns[&self.outcome];
+        if pass < self.nfeatures() {
+            y.clone()
+        }else{
+            vec![1; y.len()]
+        }
+    }
+
+    fn server_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        let feature = |i: usize| -> Vec<u64> {
+            if i == 0 {
+                vec![1; columns["payload"].len()]
+            }else{
+                columns[&self.covariates[i - 1]].clone()
+            }
+        };
+        if pass < self.nfeatures() {
+            feature(pass)
+        }else{
+            let (i, j) = self.pairs()[pass - self.nfeatures()];
+            feature(i).iter().zip(feature(j).iter()).map(|(x, z)| x * z).collect()
+        }
+    }
+
+    // The server inputs the constant feature in the first pass
+    fn count_pass(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn output<F: Fancy>(&self, f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>{
+        let m = self.nfeatures();
+        let xty = sums[..m].iter().map(|(acc, _)| acc.clone()).collect::<Vec<CrtBundle<F::Item>>>();
+
+        let mut xtx = vec![vec![sums[0].1.clone(); m]; m];
+        for i in 0..m {
+            xtx[0][i] = sums[i].1.clone();
+            xtx[i][0] = sums[i].1.clone();
+        }
+        for (k, (i, j)) in self.pairs().into_iter().enumerate() {
+            xtx[i][j] = sums[m + k].0.clone();
+            xtx[j][i] = sums[m + k].0.clone();
+        }
+
+        match self.reveal {
+            Reveal::Statistics => {
+                let mut outputs = Vec::new();
+                for i in 0..m {
+                    outputs.extend(xtx[i][i..].iter().cloned());
+                }
+                outputs.extend(xty);
+                Ok(outputs)
+            }
+            Reveal::Determinants => {
+                let mut outputs = vec![determinant(f, &xtx)?];
+                for i in 0..m {
+                    let mut a = xtx.clone();
+                    for (row, y) in a.iter_mut().zip(xty.iter()) {
+                        row[i] = y.clone();
+                    }
+                    outputs.push(determinant(f, &a)?);
+                }
+                Ok(outputs)
+            }
+        }
+    }
+
+    // A matched pair changes every entry of XᵀX and Xᵀy by at most the product
+    // of the largest values of its features.
+    fn sensitivities(&self) -> Vec<f64> {
+        assert!(self.reveal == Reveal::Statistics, "noise can only be added when revealing the statistics");
+        let max = self.max_features();
+        let m = self.nfeatures();
+        let mut sensitivities = Vec::new();
+        for i in 0..m {
+            sensitivities.extend(max[i..].iter().map(|x| x * max[i]));
+        }
+        sensitivities.extend(max.iter().map(|x| x * self.max_outcome as f64));
+        sensitivities
+    }
+
+    fn finalize(&self, values: &[i128]) -> Fit {
+        let m = self.nfeatures();
+        let mut names = vec!["intercept".to_owned()];
+        names.extend(self.covariates.iter().cloned());
+
+        let (coefficients, statistics) = match self.reveal {
+            Reveal::Determinants => {
+                let det = values[0];
+                let coefficients = values[1..].iter()
+                                              .map(|det_i| if det != 0 { *det_i as f64 / det as f64 } else { 0.0 })
+                                              .collect();
+                (coefficients, None)
+            }
+            Reveal::Statistics => {
+                let mut xtx = vec![vec![0; m]; m];
+                let mut values = values.iter();
+                for i in 0..m {
+                    for j in i..m {
+                        let x = *values.next().unwrap();
+                        xtx[i][j] = x;
+                        xtx[j][i] = x;
+                    }
+                }
+                let xty = values.cloned().collect::<Vec<i128>>();
+                (Regression::solve(&xtx, &xty), Some((xtx, xty)))
+            }
+        };
+
+        Fit{
+            names,
+            coefficients: coefficients.iter().map(|x| FixedPoint::from_f64(*x, self.digits)).collect(),
+            statistics,
+        }
+    }
+}
+
+// Determinant of a square matrix of bundles, by Laplace expansion along the first row.
+fn determinant<F: Fancy>(f: &mut F, a: &[Vec<CrtBundle<F::Item>>]) -> Result<CrtBundle<F::Item>, F::Error>{
+    if a.len() == 1 {
+        return Ok(a[0][0].clone());
+    }
+    let mut det: Option<CrtBundle<F::Item>> = None;
+    for j in 0..a.len() {
+        let minor = a[1..].iter()
+                          .map(|row| row.iter()
+                                        .enumerate()
+                                        .filter(|(c, _)| *c != j)
+                                        .map(|(_, x)| x.clone())
+                                        .collect())
+                          .collect::<Vec<Vec<CrtBundle<F::Item>>>>();
+        let term = determinant(f, &minor)?;
+        let term = f.crt_mul(&a[0][j], &term)?;
+        det = Some(match det {
+            None if j % 2 == 0 => term,
+            None => {
+                let zero = f.crt_constant_bundle(0, term.composite_modulus())?;
+                f.crt_sub(&zero, &term)?
+            }
+            Some(det) if j % 2 == 0 => f.crt_add(&det, &term)?,
+            Some(det) => f.crt_sub(&det, &term)?,
+        });
+    }
+    Ok(det.unwrap())
+}
@@ -1,12 +1,41 @@
 mod utils;
-use match_compute::util;
+use match_compute::{aggregate::{correlation::Moment, MatchAggregate, Statistic}, util};
 use crate::utils::run_client::run_client;
+use std::process;
 
 pub fn main(){
     let path = util::get_path();
     let parameters = util::parse_config(&mut path.clone());
     let (_, set_size, id_size, payload_size, max_payload, _, fake_data) = util::get_config_experiments(&parameters);
 
-    let (time, read, written) = run_client(set_size, id_size, max_payload, payload_size, fake_data);
+    match util::get_config_statistic(&parameters) {
+        Statistic::Expression => run(&util::get_config_aggregate(&parameters), set_size, id_size,
+                                    max_payload, payload_size, fake_data),
+        Statistic::Histogram => run(&util::get_config_histogram(&parameters), set_size, id_size,
+                                    max_payload, payload_size, fake_data),
+        Statistic::Covariance => run(&util::get_config_correlation(&parameters, Moment::Covariance), set_size,
+                                    id_size, max_payload, payload_size, fake_data),
+        Statistic::Correlation => run(&util::get_config_correlation(&parameters, Moment::Correlation), set_size,
+                                    id_size, max_payload, payload_size, fake_data),
+        Statistic::Regression => run(&util::get_config_regression(&parameters), set_size, id_size,
+                                    max_payload, payload_size, fake_data),
+        Statistic::Quantile => run(&util::get_config_quantile(&parameters), set_size, id_size,
+                                    max_payload, payload_size, fake_data),
+        Statistic::Membership => run(&util::get_config_membership(&parameters), set_size, id_size,
+                                    max_payload, payload_size, fake_data),
+        Statistic::Lookup => run(&util::get_config_lookup(&parameters), set_size, id_size,
+                                    max_payload, payload_size, fake_data),
+        Statistic::Jaccard => run(&util::get_config_jaccard(&parameters), set_size, id_size,
+                                    max_payload, payload_size, fake_data),
+    }
+}
 
+// The outcomes, the time and the communication are reported by run_client, which fails when
+// the client could not reach the server in time.
+fn run<A: MatchAggregate>(aggregate: &A, set_size: usize, id_size: usize, max_payload: u64,
+                        payload_size: usize, fake_data: bool){
+    if let Err(e) = run_client(aggregate, set_size, id_size, max_payload, payload_size, fake_data) {
+        println!("Receiver :: Failed: {}", e);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,74 @@
+// Hands out the batches of megabins to a client worker process on another machine
+use popsicle::psty_payload::ReceiverState;
+
+use scuttlebutt::{AbstractChannel, Block, TrackChannel, SymChannel};
+
+use match_compute::util::{self, format, remote::{self, Job, SealedChannel}, StateBatches};
+use std::{
+    net::TcpStream,
+    time::SystemTime,
+    path::PathBuf,
+    io::Error,
+};
+use bincode;
+
+use crate::utils::client_thread::read_states;
+
+fn coordinator_protocol(mut channel: TrackChannel<SealedChannel<SymChannel<TcpStream>>>, path: &mut PathBuf, pass: &str,
+                        worker: &str, peer: &str, payload_size: usize, batches: &StateBatches<ReceiverState>)
+                        -> (f64, f64){
+    let start = SystemTime::now();
+
+    Job{
+        pass: pass.to_owned(),
+        payload_size,
+        peer: remote::pairing_address(peer),
+        deltas: Vec::new(),
+    }.send(&mut channel).unwrap();
+
+    // The worker requests the states of the batches the server worker hands out,
+    // and sends back the garbled partial results.
+    path.push(pass);
+    while channel.read_bool().unwrap() {
+        let batch = channel.read_usize().unwrap();
+        path.push(util::batch_name(batch));
+        let states = batches.take(batch).unwrap_or_else(|| read_states(path));
+        remote::send_bytes(&mut channel, &bincode::serialize(&states).unwrap()).unwrap();
+
+        let (_, acc) = format::decode_bundles(&remote::receive_bytes(&mut channel).unwrap()).unwrap();
+        let (_, sum_weights) = format::decode_bundles(&remote::receive_bytes(&mut channel).unwrap()).unwrap();
+        util::write_partial_outputs(path, &acc, &sum_weights);
+        path.pop();
+        batches.finish(batch, path);
+    }
+    path.pop();
+    let (read, written) = remote::receive_communication(&mut channel).unwrap();
+
+    println!(
+        "Receiver Worker {} :: total computation time: {} ms", worker,
+        start.elapsed().unwrap().as_millis()
+    );
+    println!(
+        "Receiver Worker {} :: total computation communication (read): {:.2} Mb", worker,
+        read / 1000.0
+    );
+    println!(
+        "Receiver Worker {} :: total computation communication (write): {:.2} Mb", worker,
+        written / 1000.0
+    );
+    (read, written)
+}
+
+pub fn client_coordinator(path: &mut PathBuf, pass: &str, worker: &str, peer: &str, payload_size: usize,
+                        batches: &StateBatches<ReceiverState>, key: Block) -> Result<(f64, f64), Error>{
+    match TcpStream::connect(worker) {
+        Ok(stream) => {
+            let channel = TrackChannel::new(SealedChannel::coordinator(SymChannel::new(stream), key)?);
+            Ok(coordinator_protocol(channel, path, pass, worker, peer, payload_size, batches))
+        },
+        Err(e) => {
+            println!("Failed to connect to worker {}: {}", worker, e);
+            Err(e)
+        }
+    }
+}
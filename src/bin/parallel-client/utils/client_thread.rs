@@ -2,32 +2,23 @@ use popsicle::psty_payload::{Receiver, ReceiverState};
 use popsicle::psty_utils::psty_large::{
     ReceiverMegabins,
 };
-use scuttlebutt::{AesRng, TrackChannel, SymChannel};
+use scuttlebutt::{AbstractChannel, AesRng, TrackChannel, SymChannel};
 
-use match_compute::util;
+use match_compute::util::{self, StateBatches};
 use std::{
     fs::{File},
-    io::{Write, Read},
+    io::{Read},
     net::{TcpStream},
-    time::SystemTime,
+    time::{Duration, SystemTime},
     path::PathBuf,
     io::Error,
+    sync::Arc,
 };
 
 use bincode;
-use serde_json;
-
-fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>,
-    path: &mut PathBuf, thread_id: usize, payload_size: usize)
-    ->(f64, f64){
-    let start = SystemTime::now();
-    println!("Receiver Thread {} Starting computation", thread_id);
-    let mut rng = AesRng::new();
-
-    let mut thread_path = "thread".to_owned();
-    thread_path.push_str(&thread_id.to_string());
-    path.push(thread_path);
 
+/// Read the states of a batch back from its folder.
+pub fn read_states(path: &mut PathBuf) -> Vec<ReceiverState> {
     path.push("states.txt");
     let path_str = path.clone().into_os_string().into_string().unwrap();
     let mut file_states = File::open(path_str).unwrap();
@@ -37,16 +28,37 @@ fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>,
 
     file_states.read_to_end(&mut buff).unwrap();
 
-    let states: Vec<ReceiverState> = bincode::deserialize(&mut buff).unwrap();
-    let nmegabins = states.len();
-    let mut megabins = ReceiverMegabins{
-        states,
-        nmegabins,
-    };
+    bincode::deserialize(&mut buff).unwrap()
+}
+
+fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>,
+    path: &mut PathBuf, pass: &str, thread_id: usize, payload_size: usize, batches: &StateBatches<ReceiverState>)
+    ->(f64, f64){
+    let start = SystemTime::now();
+    println!("Receiver Thread {} Starting computation", thread_id);
+    let mut rng = AesRng::new();
 
     let mut psi = Receiver::init(&mut channel, &mut rng).unwrap();
     let p =  fancy_garbling::util::primes_with_width(payload_size as u32).len() + 1;
-    let (acc, sum_weights) = psi.compute_circuit(p, payload_size, &mut megabins,&mut channel, &mut rng).unwrap();
+
+    // The server thread hands out the batches it pulls from its queue, until it is empty
+    path.push(pass);
+    while channel.read_bool().unwrap() {
+        let batch = channel.read_usize().unwrap();
+        path.push(util::batch_name(batch));
+        // The states are read back from the folder of the batch when they were written to disk
+        let states = batches.take(batch).unwrap_or_else(|| read_states(path));
+        let nmegabins = states.len();
+        let mut megabins = ReceiverMegabins{
+            states,
+            nmegabins,
+        };
+        let (acc, sum_weights) = psi.compute_circuit(p, payload_size, &mut megabins,&mut channel, &mut rng).unwrap();
+        util::write_partial_outputs(path, &acc, &sum_weights);
+        path.pop();
+        batches.finish(batch, path);
+    }
+    path.pop();
 
     println!(
         "Receiver Thread {} :: total circuit building & computation time: {} ms", thread_id,
@@ -61,38 +73,19 @@ fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>,
         channel.kilobits_written() / 1000.0
     );
 
-    path.push("output_aggregate.txt");
-    let path_str = path.clone().into_os_string().into_string().unwrap();
-    let mut file_aggregate = File::create(path_str).unwrap();
-    path.pop();
-
-
-    path.push("output_sum_weights.txt");
-    let path_str = path.clone().into_os_string().into_string().unwrap();
-    let mut file_sum_weights = File::create(path_str).unwrap();
-    path.pop();
-
-    let aggregate_json = serde_json::to_string(&util::crt_to_wires(&acc)).unwrap();
-    let sum_weights_json = serde_json::to_string(&util::crt_to_wires(&sum_weights)).unwrap();
-
-    file_aggregate.write(aggregate_json.as_bytes()).unwrap();
-    file_sum_weights.write(sum_weights_json.as_bytes()).unwrap();
-
     let total_read = channel.kilobits_read() / 1000.0;
     let total_written = channel.kilobits_written() / 1000.0;
     (total_read, total_written)
 }
 
-pub fn client_thread(path: &mut PathBuf, address: &str, thread_id: usize,
-                    payload_size: usize)
+pub fn client_thread(path: &mut PathBuf, pass: &str, address: &str, thread_id: usize,
+                    payload_size: usize, batches: Arc<StateBatches<ReceiverState>>, timeout: Duration)
     -> Result<(f64, f64), Error>{
-    let port_prefix = format!("{}{}", address,":300");
-    let port = format!("{}{}", port_prefix, thread_id.to_string());
-
-    match TcpStream::connect(port) {
+    // The server thread starts listening once the server is done bucketizing
+    match util::connect(&util::thread_address(address, thread_id), timeout) {
         Ok(stream) => {
             let channel = TrackChannel::new(SymChannel::new(stream));
-            Ok(client_protocol(channel, path, thread_id, payload_size))
+            Ok(client_protocol(channel, path, pass, thread_id, payload_size, &batches))
         },
         Err(e) => {
             println!("Failed to connect: {}", e);
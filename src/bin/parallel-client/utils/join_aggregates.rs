@@ -1,65 +1,109 @@
-use popsicle::psty_payload::{Receiver};
-use match_compute::util;
-use fancy_garbling::Wire;
-use scuttlebutt::{AesRng, SymChannel, TrackChannel};
+use match_compute::{
+    aggregate::MatchAggregate,
+    circuit,
+    output::{self, Outcome, OutputMode, Recipient},
+    util,
+};
+use fancy_garbling::{CrtGadgets, Fancy};
+use scuttlebutt::{AbstractChannel, SymChannel, TrackChannel};
 
 use std::{
-    fs::{File, write, read_to_string},
+    collections::HashMap,
     net::{TcpStream},
-    time::SystemTime,
+    time::{Duration, SystemTime},
     io::Error,
     path::PathBuf,
 };
-use serde_json;
 
 
-fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>,
-    path:&mut PathBuf, nthreads: usize, _precision: u32, payload_size: usize)
-    -> (u128, f64, f64){
+fn client_protocol<A: MatchAggregate>(mut channel: TrackChannel<SymChannel<TcpStream>>,
+    path:&mut PathBuf, parameters: &HashMap<String, String>, aggregate: &mut A, set_size: usize,
+    _precision: u32, nthread: usize, category: usize, min_intersection: u128, last_round: bool)
+    -> (Outcome<Vec<i128>>, f64, f64){
     let start = SystemTime::now();
-    let mut rng = AesRng::new();
-
-    let mut aggregates= Vec::new();
-    let mut sum_weights= Vec::new();
-    for thread_id in 0..nthreads{
-        let mut thread_path = "thread".to_owned();
-        thread_path.push_str(&thread_id.to_string());
-        path.push(thread_path);
-
-        path.push("output_aggregate.txt");
-        let path_str = path.clone().into_os_string().into_string().unwrap();
-        let partial_aggregate: Vec<Vec<Wire>> = serde_json::from_str(&read_to_string(path_str).unwrap()).unwrap();
-        path.pop();
-
-        path.push("output_sum_weights.txt");
-        let path_str = path.clone().into_os_string().into_string().unwrap();
-        let partial_sum_weights: Vec<Vec<Wire>> = serde_json::from_str(&read_to_string(path_str).unwrap()).unwrap();
-        path.pop();
-
-        aggregates.append(&mut util::wires_to_crt(&partial_aggregate));
-        sum_weights.append(&mut util::wires_to_crt(&partial_sum_weights));
-
-        path.pop();
+    let (mechanism, epsilon, delta) = util::get_config_noise(parameters);
+    // The outputs of the rounds before the last are revealed to both parties
+    let (output_mode, recipient) = if last_round {
+        (util::get_config_output_mode(parameters), util::get_config_output_recipient(parameters))
+    }else{
+        (OutputMode::Reveal, Recipient::Both)
+    };
+
+    // The statistics that depend on the sizes of the sets have them exchanged in the clear
+    if last_round && aggregate.reveals_set_sizes() {
+        let server_size = channel.read_usize().unwrap();
+        channel.write_usize(set_size).unwrap();
+        channel.flush().unwrap();
+        aggregate.set_sizes(set_size, server_size);
     }
 
-    let mut psi = Receiver::init(&mut channel, &mut rng).unwrap();
-    let p =  fancy_garbling::util::primes_with_width(payload_size as u32).len() + 1;
-    let weighted_mean = psi.join_circuits(p, &mut aggregates,
-                            &mut sum_weights, &mut channel,&mut rng).unwrap();
-    println!("weighted_mean: {:?}", weighted_mean);
-
-
-    path.pop();
-    path.push("result.txt");
-    let path_str = path.clone().into_os_string().into_string().unwrap();
-    path.pop();
+    let mut ev = circuit::join_evaluator(&mut channel).unwrap();
+
+    // The partial results of every pass are summed across batches in a reduction tree
+    let sums = (0..aggregate.npasses())
+                .map(|pass| util::reduce_partial_outputs(path, &util::pass_name(category, pass), nthread, A::combine))
+                .collect::<Vec<_>>();
+    let mut outputs = aggregate.output(&mut ev, &sums).unwrap();
+
+    // Both parties add a share of the noise to every output, each calibrated to the full
+    // privacy budget. The budget is split across the outputs that compose.
+    if let Some(mechanism) = &mechanism {
+        let noutputs = aggregate.ncompositions() as f64;
+        let sensitivities = aggregate.sensitivities();
+        for (x, sensitivity) in outputs.iter_mut().zip(sensitivities.iter()){
+            *x = circuit::evaluator_noise(&mut ev, x, mechanism, *sensitivity,
+                                        epsilon / noutputs, delta / noutputs).unwrap();
+        }
+    }
 
-    let _ = File::create(path_str.clone()).unwrap();
+    // The size of the intersection is the sum of weights of a pass where every weight
+    // is set to one: a pass of the statistic, or the count pass run for this purpose.
+    let mut revealed = true;
+    if min_intersection > 0 {
+        let count = match aggregate.count_pass() {
+            Some(pass) => sums[pass].1.clone(),
+            None => util::reduce_partial_outputs(path, &util::count_pass_name(category), nthread, circuit::add_sums).1,
+        };
+        let reveal = circuit::threshold(&mut ev, &count, min_intersection).unwrap();
+        for x in outputs.iter_mut(){
+            *x = circuit::gate(&mut ev, &reveal, x).unwrap();
+        }
+        revealed = ev.output(&reveal).unwrap().unwrap() == 1;
+    }
 
-    let mut output_write = "Weighted Mean: ".to_owned();
-    output_write.push_str(&weighted_mean.to_string());
+    // In shared mode the outputs are masked by the sender and are the receiver's shares.
+    // They are masked as well when the result is only delivered to the sender.
+    let q = outputs[0].composite_modulus();
+    if output_mode == OutputMode::Shared || !recipient.receiver_learns() {
+        for x in outputs.iter_mut(){
+            *x = circuit::evaluator_share(&mut ev, x).unwrap();
+        }
+    }
+    let values = outputs.iter()
+                        .map(|x| ev.crt_output(x).unwrap().unwrap())
+                        .collect::<Vec<u128>>();
+
+    // Let the sender know whether the result was suppressed,
+    // and forward it the outputs when it is a recipient
+    channel.write_bool(!revealed).unwrap();
+    channel.flush().unwrap();
+    if revealed && output_mode == OutputMode::Reveal && recipient.sender_learns() {
+        output::send_outputs(&mut channel, &values).unwrap();
+    }
 
-    write(path_str, output_write).expect("Unable to write file");
+    let outcome = if !revealed {
+        println!("Receiver :: Result suppressed, the intersection is smaller than {}", min_intersection);
+        Outcome::Suppressed
+    }else if output_mode == OutputMode::Shared {
+        Outcome::Shares{
+            modulus: q,
+            values,
+        }
+    }else if recipient.receiver_learns() {
+        Outcome::Revealed(output::decode(&values, q))
+    }else{
+        Outcome::Hidden
+    };
 
     println!(
         "Receiver :: total Joining threads results time: {} ms",
@@ -76,18 +120,22 @@ fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>,
 
     let total_read = channel.kilobits_read() / 1000.0;
     let total_written = channel.kilobits_written() / 1000.0;
-    (weighted_mean, total_read, total_written)
+    (outcome, total_read, total_written)
 }
 
-pub fn join_aggregates(path:&mut PathBuf, address: &str,
-    nthreads: usize, precision: u32, payload_size: usize)
-    -> Result<(u128, f64, f64), Error>{
+/// Join the partial results of a round of the statistic. Returns the decoded output values.
+/// `set_size` is the number of records of the client, padding excluded. The client waits
+/// up to `timeout` for the server to be listening.
+pub fn join_aggregates<A: MatchAggregate>(path:&mut PathBuf, parameters: &HashMap<String, String>,
+    aggregate: &mut A, set_size: usize, address: &str, precision: u32, nthread: usize, category: usize,
+    min_intersection: u128, last_round: bool, timeout: Duration) -> Result<(Outcome<Vec<i128>>, f64, f64), Error>{
     let port_prefix = format!("{}{}", address,":3000");
 
-    match TcpStream::connect(port_prefix) {
+    match util::connect(&port_prefix, timeout) {
         Ok(stream) => {
             let channel = TrackChannel::new(SymChannel::new(stream));
-            Ok(client_protocol(channel, path, nthreads, precision, payload_size))
+            Ok(client_protocol(channel, path, parameters, aggregate, set_size, precision, nthread, category,
+                            min_intersection, last_round))
         },
         Err(e) => {
             println!("Failed to connect: {}", e);
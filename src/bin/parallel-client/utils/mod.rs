@@ -1,5 +1,6 @@
 pub mod run_client;
 pub mod join_aggregates;
 pub mod client_thread;
+pub mod client_coordinator;
 pub mod prepare_files;
 pub mod test;
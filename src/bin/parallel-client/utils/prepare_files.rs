@@ -1,25 +1,44 @@
 // Bucketize Data and Seperate it among threads
 use popsicle::psty_payload::{Receiver, ReceiverState};
-use match_compute::util;
+use match_compute::util::{self, manifest::Manifest, StateBatches, StatesMode};
 
-use scuttlebutt::{AesRng, Block512, TrackChannel, SymChannel};
+use scuttlebutt::{AbstractChannel, AesRng, Block512, TrackChannel, SymChannel};
 
 use std::{
     fs::{File, create_dir_all},
     io::{Write},
     net::{TcpStream},
-    time::SystemTime,
+    time::{Duration, SystemTime},
     path::PathBuf,
     io::Error,
 };
 
 use bincode;
 
-fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>, path: &mut PathBuf, nthread: usize,
-                    megasize: usize, ids: &[Vec<u8>], payloads: &[Block512], client_padding: usize)
-                    ->(f64, f64){
+fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>, path: &mut PathBuf, pass: &str, batch_size: usize,
+                    states_mode: &StatesMode, round: usize, checkpoint: Option<Manifest>, megasize: usize,
+                    ids: &[Vec<u8>], payloads: &[Block512], client_padding: usize)
+                    ->(f64, f64, StateBatches<ReceiverState>){
     let start = SystemTime::now();
 
+    // The pass is resumed when both parties have a checkpoint of it: the server checks that
+    // the manifests describe the same pass and sends back the batches that both computed.
+    channel.write_bool(checkpoint.is_some()).unwrap();
+    channel.flush().unwrap();
+    if channel.read_bool().unwrap() {
+        checkpoint.unwrap().send(&mut channel).unwrap();
+        let manifest = Manifest::receive(&mut channel).unwrap();
+        println!("Receiver :: Resuming {}, {} batches left", pass, manifest.remaining().len());
+
+        let batches = StateBatches::resume(manifest);
+        path.push(pass);
+        batches.write_manifest(path);
+        path.pop();
+        let total_read = channel.kilobits_read() / 1000.0;
+        let total_written = channel.kilobits_written() / 1000.0;
+        return (total_read, total_written, batches);
+    }
+
     let mut rng = AesRng::new();
     let (ids_pad, payloads_pad) = util::pad_data(ids, payloads, client_padding, &mut rng);
 
@@ -28,30 +47,35 @@ fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>, path: &mut
     let mut psi = Receiver::init(&mut channel, &mut rng).unwrap();
     let megabins = psi.bucketize_data_large(&ids_pad, &payloads_pad, megasize, &mut channel, &mut rng).unwrap();
 
-    let megabin_per_thread = ((megabins.nmegabins as f32)/(nthread as f32)).ceil() as usize;
+    // The megabins are grouped into batches that the threads are handed out by the server,
+    // which groups them the same way.
+    let batches = StateBatches::new(megabins.states, batch_size, round);
 
-    println!("Number of megabins per thread {:?}", megabin_per_thread);
+    println!("Number of batches {:?}", batches.len());
 
-    let states_per_thread:Vec<&[ReceiverState]> = megabins.states.chunks(megabin_per_thread).collect();
-    // Create files and folders with the data that each thread should handle.
-    for i in 0 ..nthread{
-        let mut thread_path = "thread".to_owned();
-        thread_path.push_str(&i.to_string());
-        path.push(thread_path);
+    // Create the folders of every batch, where the partial results are written.
+    // The states are only written there when they are checkpointed on disk.
+    path.push(pass);
+    for batch in 0..batches.len(){
+        path.push(util::batch_name(batch));
 
         let path_str = path.clone().into_os_string().into_string().unwrap();
         let _ = create_dir_all(path_str);
 
-        path.push("states.txt");
-        let path_str = path.clone().into_os_string().into_string().unwrap();
-        let mut file_states = File::create(path_str).unwrap();
-        path.pop();
+        if *states_mode == StatesMode::Files {
+            path.push("states.txt");
+            let path_str = path.clone().into_os_string().into_string().unwrap();
+            let mut file_states = File::create(path_str).unwrap();
+            path.pop();
 
-        let state_json = bincode::serialize(&states_per_thread[i]).unwrap();
-        file_states.write(&state_json).unwrap();
+            let state_json = bincode::serialize(&batches.take(batch).unwrap()).unwrap();
+            file_states.write(&state_json).unwrap();
+        }
 
         path.pop();
     }
+    batches.write_manifest(path);
+    path.pop();
     println!(
         "Receiver :: Bucketization time : {} ms",
         start.elapsed().unwrap().as_millis()
@@ -67,18 +91,20 @@ fn client_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>, path: &mut
 
     let total_read = channel.kilobits_read() / 1000.0;
     let total_written = channel.kilobits_written() / 1000.0;
-    (total_read, total_written)
+    (total_read, total_written, batches)
 }
 
-pub fn prepare_files(path: &mut PathBuf, address: &str, nthread: usize, megasize: usize,
-                    ids: &[Vec<u8>], payloads: &[Block512], client_padding: usize)
-                    -> Result<(f64, f64), Error>{
-    let address = format!("{}{}", address,":3000");
-
-    match TcpStream::connect(address) {
+/// Bucketize the data of `pass` with the server and split its megabins into batches.
+/// Returns the communication and the batches, whose states are kept in memory unless they
+/// were written to disk.
+pub fn prepare_files(path: &mut PathBuf, pass: &str, address: &str, batch_size: usize, states_mode: &StatesMode,
+                    round: usize, checkpoint: Option<Manifest>, megasize: usize, ids: &[Vec<u8>], payloads: &[Block512], client_padding: usize,
+                    timeout: Duration) -> Result<(f64, f64, StateBatches<ReceiverState>), Error>{
+    // The server starts listening once it is done with the previous pass
+    match util::connect(&format!("{}{}", address,":3000"), timeout) {
         Ok(stream) => {
             let channel = TrackChannel::new(SymChannel::new(stream));
-            Ok(client_protocol(channel, path, nthread, megasize, ids, payloads, client_padding))
+            Ok(client_protocol(channel, path, pass, batch_size, states_mode, round, checkpoint, megasize, ids, payloads, client_padding))
         },
         Err(e) => {
             println!("Failed to connect: {}", e);
@@ -1,27 +1,38 @@
-use match_compute::util;
+use match_compute::{
+    aggregate::MatchAggregate,
+    output::{self, Outcome, OutputMode},
+    util::{self, tuning::{self, Resources}, StateBatches, StatesMode},
+};
 
 use crate::utils::{
     prepare_files::prepare_files,
     client_thread::client_thread,
+    client_coordinator::client_coordinator,
     join_aggregates::join_aggregates,
     test::*,
 };
 
 
+use popsicle::psty_payload::ReceiverState;
+use scuttlebutt::{Block, Block512, SymChannel};
 use std::{
+    io::Error,
+    panic,
     time::{Duration},
     time::SystemTime,
     thread,
+    path::PathBuf,
+    sync::{mpsc, Arc},
 };
 
 
-pub fn run_client(set_size: usize, id_size: usize, max_payload:u64,
-                 payload_size: usize, fake_data: bool) -> (u64, f64, f64){
+pub fn run_client<A: MatchAggregate>(aggregate: &A, set_size: usize, id_size: usize, max_payload:u64,
+                 payload_size: usize, fake_data: bool) -> Result<(Vec<Outcome<A::Output>>, u64, f64, f64), Error>{
 
     let start = SystemTime::now();
     let mut path = util::get_path();
     let parameters = util::parse_config(&mut path.clone());
-    let (address, client_path, sleeptime, precision, nthread,
+    let (address, client_path, precision, nthread,
         megasize, client_padding, id_position, payload_position) = util::get_config_client(&parameters);
 
     let (ids, payloads) = if fake_data == true {
@@ -32,39 +43,95 @@ pub fn run_client(set_size: usize, id_size: usize, max_payload:u64,
             util::parse_files(id_position, payload_position, &client_path)
         };
 
-   // Bucketize the data and split into megabins that are distributed among threads
-   path.push("bin/parallel-client/data");
-   let (read_init, written_init) = prepare_files(&mut path, &address, nthread, megasize,
-                                                &ids, &payloads, client_padding).unwrap();
+    // The columns the statistic is computed from. The aggregate was checked
+    // statically for intersections of at most set_size records.
+    assert!(ids.len() <= set_size, "the client has more than set_size records");
+    // The ids are shared with the thread that bucketizes the passes
+    let ids = Arc::new(ids);
+    let specs = util::get_config_columns(&parameters, "client_columns");
+    let table = util::read_columns(&specs, &payloads, max_payload, &client_path, fake_data);
+    let ones = util::int_vec_block512(vec![1; ids.len()]);
 
-   // Wait for the server to be done
-   let duration = Duration::from_secs(sleeptime);
-   thread::sleep(duration);
+    let (ncategories, _) = util::get_config_group_by(&parameters);
+    let batch_size = util::get_config_batch_size(&parameters);
+    let states_mode = util::get_config_states_mode(&parameters);
+    let resume = util::get_config_resume(&parameters);
+    let workers = util::get_config_workers(&parameters);
+    let worker_key = util::get_config_worker_key(&parameters);
+    assert!(workers.0.is_empty() || worker_key.is_some(), "the coordinator needs a worker_key to speak to its workers");
+    let min_intersection = util::get_config_min_intersection(&parameters);
 
-    // Each thread handles its own megabins and speaks to the appropriate other party thread
-    // via a dedicated port. The partial results of this computation are garbled and
-    // stored into appropriate files. They are handled later to produce the correct output.
-    let mut handle = Vec::new();
-    for i in 0..nthread {
-        let mut path_thread = path.clone();
-        let address_thread = address.clone();
-       handle.push(thread::spawn(move || {
-           client_thread(&mut path_thread, &address_thread, i, payload_size).unwrap()
-       }));
-   }
-   let mut results = Vec::new();
-   for thread in handle {
-        results.push(thread.join().unwrap()); // maybe consider handling errors propagated from the thread here
+    let duration = util::get_config_connect_timeout(&parameters);
+
+    // The parties agree on the number of threads and the size of the megabins before the first pass
+    let stream = util::connect(&format!("{}{}", address,":3000"), duration)?;
+    let resources = Resources::local(nthread, util::get_config_memory_budget(&parameters), ids.len() + client_padding);
+    let tuning = tuning::agree_client(&mut SymChannel::new(stream), &resources, megasize, payload_size, batch_size)?;
+    tuning.log("Receiver");
+    let (nthread, megasize) = (tuning.nthread, tuning.megasize);
+
+    path.push("bin/parallel-client/data");
+    // The server masks its payloads to one category at a time: the computation is repeated
+    // for every category in the domain agreed in the configuration.
+    let mut outcomes = Vec::new();
+    let mut total_read = 0.0;
+    let mut total_written = 0.0;
+    for category in 0..ncategories {
+        println!("Receiver :: Category {}", category);
+        // Statistics computed over several rounds start afresh for every category
+        let mut aggregate = aggregate.clone();
+        let nrounds = aggregate.nrounds();
+        let mut outcome = Outcome::Hidden;
+        for round in 0..nrounds {
+            // The passes that do not depend on the previous rounds are only computed in the first
+            let mut passes = (0..aggregate.npasses())
+                                .filter(|pass| round == 0 || aggregate.recompute(*pass))
+                                .map(|pass| (util::pass_name(category, pass),
+                                             util::int_vec_block512(aggregate.client_column(pass, &table))))
+                                .collect::<Vec<(String, Vec<Block512>)>>();
+            // When a minimum intersection size is required, the server runs an additional pass
+            // with unit weights to count the matched records of the category, unless a pass
+            // of the statistic does
+            if min_intersection > 0 && round == 0 && aggregate.count_pass().is_none() {
+                passes.push((util::count_pass_name(category), ones.clone()));
+            }
+            let (read, written) = run_passes(&mut path, passes, &address, nthread, batch_size, &states_mode, round, resume,
+                                            &workers, worker_key, megasize, &ids, client_padding, payload_size, duration)?;
+            total_read = total_read + read;
+            total_written = total_written + written;
+
+           // The partial results are joined and the output is produced, once the server listens
+            let (values, read_final, written_final) = join_aggregates(&mut path, &parameters, &mut aggregate,
+                                                    ids.len(), &address, precision, nthread, category,
+                                                    min_intersection, round + 1 == nrounds, duration)?;
+            total_read = total_read + read_final;
+            total_written = total_written + written_final;
+
+            match values {
+                Outcome::Revealed(values) if round + 1 < nrounds => aggregate.next_round(&values),
+                values => {
+                    outcome = values.map(|values| aggregate.finalize(&values));
+                    break;
+                }
+            }
+        }
+        println!("{}: {}", aggregate.name(), outcome);
+        outcomes.push(outcome);
     }
-   // The partial results are joined and the output is produced
-    thread::sleep(duration);
-    let (_result_cardinality, read_final, written_final) = join_aggregates(&mut path, &address, nthread, precision, payload_size).unwrap();
-
-    let mut total_read = read_final + read_init;
-    let mut total_written = written_final + written_init;
-    for (r, w) in results{
-        total_read = total_read + r;
-        total_written = total_written + w;
+
+    // The statistics are written to result.txt, or the shares to share.txt
+    let output_mode = util::get_config_output_mode(&parameters);
+    if output_mode == OutputMode::Shared || util::get_config_output_recipient(&parameters).receiver_learns() {
+        output::write_outcomes(&mut path, &output_mode, &aggregate.name(), &outcomes);
+    }
+    // The statistics that are tables of records are also written to result.csv
+    let tables = outcomes.iter()
+                         .map(|outcome| match outcome {
+                             Outcome::Revealed(output) => aggregate.csv(output),
+                             _ => None,
+                         }).collect::<Vec<Option<Vec<String>>>>();
+    if tables.iter().any(|table| table.is_some()) {
+        output::write_csv(&mut path, &tables);
     }
 
     println!("TOTAL TIME in {} s",start.elapsed().unwrap().as_secs());
@@ -73,6 +140,99 @@ pub fn run_client(set_size: usize, id_size: usize, max_payload:u64,
 
     // clear_results(&parameters,&mut path, &ids, &payloads, precision, fake_data);
     println!("Experiment done !");
-    thread::sleep(duration);
-    (start.elapsed().unwrap().as_secs(), total_read, total_written)
+    Ok((outcomes, start.elapsed().unwrap().as_secs(), total_read, total_written))
+}
+
+/// Run the passes of a round, as the server does: the passes are bucketized one after the other
+/// by a separate thread, at most one pass ahead of the pass whose batches are computed.
+/// Returns the communication of the round, or the first error a pass failed with.
+fn run_passes(path: &mut PathBuf, passes: Vec<(String, Vec<Block512>)>, address: &str, nthread: usize, batch_size: usize,
+            states_mode: &StatesMode, round: usize, resume: bool, workers: &(Vec<String>, Vec<String>),
+            worker_key: Option<Block>, megasize: usize,
+            ids: &Arc<Vec<Vec<u8>>>, client_padding: usize, payload_size: usize, duration: Duration) -> Result<(f64, f64), Error>{
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let bucketization = {
+        let mut path = path.clone();
+        let address = address.to_owned();
+        let states_mode = states_mode.clone();
+        let ids = Arc::clone(ids);
+        thread::spawn(move || {
+            for (pass, payloads) in passes {
+                // A resumed run picks the pass up from its manifest when the server can as well
+                let checkpoint = if resume { util::read_checkpoint(&mut path, &pass, round, &states_mode) } else { None };
+
+                // Bucketize the data and split into batches of megabins that are distributed among threads
+                let prepared = prepare_files(&mut path, &pass, &address, batch_size, &states_mode, round, checkpoint,
+                                            megasize, &ids, &payloads, client_padding, duration);
+                // The round stops at the first pass that fails, and so does the bucketization
+                let failed = prepared.is_err();
+                if sender.send((pass, prepared)).is_err() || failed {
+                    break;
+                }
+            }
+        })
+    };
+    let mut total_read = 0.0;
+    let mut total_written = 0.0;
+    for (pass, prepared) in receiver {
+        let (read, written, batches) = prepared?;
+        let (r, w) = run_pass(path, &pass, address, nthread, workers, worker_key, payload_size, Arc::new(batches), duration)?;
+        total_read = total_read + read + r;
+        total_written = total_written + written + w;
+    }
+    bucketization.join().unwrap();
+    Ok((total_read, total_written))
+}
+
+fn run_pass(path: &mut PathBuf, pass: &str, address: &str, nthread: usize, workers: &(Vec<String>, Vec<String>),
+            worker_key: Option<Block>, payload_size: usize, batches: Arc<StateBatches<ReceiverState>>, duration: Duration) -> Result<(f64, f64), Error>{
+    // Each thread computes the batches of megabins the server thread it speaks to hands out,
+    // via a dedicated port. The partial results of this computation are garbled and
+    // stored into the folder of their batch. They are handled later to produce the correct output.
+    // When there are fewer batches left than threads, the threads left without a batch are not run,
+    // as on the server side.
+    // With worker processes on other machines, the threads hand out the batches to the workers instead.
+    let mut total_read = 0.0;
+    let mut total_written = 0.0;
+    let mut handle = Vec::new();
+    let (client_workers, server_workers) = workers;
+    if client_workers.is_empty() {
+        for i in 0..util::nworkers(nthread, batches.nremaining()) {
+            let mut path_thread = path.clone();
+            let pass_thread = pass.to_owned();
+            let address_thread = address.to_owned();
+            let batches_thread = Arc::clone(&batches);
+            handle.push(thread::spawn(move || {
+                client_thread(&mut path_thread, &pass_thread, &address_thread, i, payload_size, batches_thread, duration)
+            }));
+        }
+    }else{
+        for i in 0..util::nworkers(client_workers.len(), batches.nremaining()) {
+            let mut path_thread = path.clone();
+            let pass_thread = pass.to_owned();
+            let worker = client_workers[i].clone();
+            let peer = server_workers[i].clone();
+            let batches_thread = Arc::clone(&batches);
+            handle.push(thread::spawn(move || {
+                client_coordinator(&mut path_thread, &pass_thread, &worker, &peer, payload_size, &batches_thread,
+                                   worker_key.unwrap())
+            }));
+        }
+    }
+    // A thread that could not reach its peer fails the pass with its error, once every thread is done
+    let mut failure = None;
+    for thread in handle {
+        match thread.join() {
+            Ok(Ok((r, w))) => {
+                total_read = total_read + r;
+                total_written = total_written + w;
+            },
+            Ok(Err(e)) => failure = failure.or(Some(e)),
+            Err(e) => panic::resume_unwind(e),
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok((total_read, total_written)),
+    }
 }
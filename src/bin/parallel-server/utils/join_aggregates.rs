@@ -1,60 +1,119 @@
-use popsicle::psty_payload::{Sender};
-
-use fancy_garbling::{
-    CrtBundle,
-    Wire,
+use match_compute::{
+    aggregate::MatchAggregate,
+    circuit,
+    output::{self, Outcome, OutputMode, Recipient},
+    util,
 };
-use scuttlebutt::{AesRng, SymChannel, TrackChannel};
+
+use fancy_garbling::{CrtGadgets, Fancy};
+use scuttlebutt::{AbstractChannel, SymChannel, TrackChannel};
 
 use std::{
-    fs::{read_to_string},
-    net::{TcpListener, TcpStream},
+    collections::HashMap,
+    net::{TcpStream},
     time::SystemTime,
     path::PathBuf,
 };
-use serde_json;
-
-
-fn wires_to_crt(v: &[Vec<Wire>])-> Vec<CrtBundle<Wire>>{
-    v.into_iter()
-     .map(|c| CrtBundle::new(c.to_vec())).collect()
-}
 
 
-fn server_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>, path:&mut PathBuf, nthreads: usize) {
+fn server_protocol<A: MatchAggregate>(mut channel: TrackChannel<SymChannel<TcpStream>>, path:&mut PathBuf,
+                    parameters: &HashMap<String, String>, aggregate: &mut A, set_size: usize,
+                    nthread: usize, category: usize, min_intersection: u128, last_round: bool) -> Outcome<Vec<i128>> {
     let start = SystemTime::now();
-    let mut rng = AesRng::new();
+    let (mechanism, epsilon, delta) = util::get_config_noise(parameters);
+    // The outputs of the rounds before the last are revealed to both parties
+    let (output_mode, recipient) = if last_round {
+        (util::get_config_output_mode(parameters), util::get_config_output_recipient(parameters))
+    }else{
+        (OutputMode::Reveal, Recipient::Both)
+    };
 
     path.push("delta.txt");
     let path_delta = path.clone().into_os_string().into_string().unwrap();
     path.pop();
 
-    let mut aggregates= Vec::new();
-    let mut sum_weights= Vec::new();
-    for thread_id in 0..nthreads{
-        let mut thread_path = "thread".to_owned();
-        thread_path.push_str(&thread_id.to_string());
-        path.push(thread_path);
-
-        path.push("output_aggregate.txt");
-        let path_str = path.clone().into_os_string().into_string().unwrap();
-        let partial_aggregate: Vec<Vec<Wire>> = serde_json::from_str(&read_to_string(path_str).unwrap()).unwrap();
-        path.pop();
-
-        path.push("output_sum_weights.txt");
-        let path_str = path.clone().into_os_string().into_string().unwrap();
-        let partial_sum_weights: Vec<Vec<Wire>> = serde_json::from_str(&read_to_string(path_str).unwrap()).unwrap();
-        path.pop();
+    // The statistics that depend on the sizes of the sets have them exchanged in the clear
+    if last_round && aggregate.reveals_set_sizes() {
+        channel.write_usize(set_size).unwrap();
+        channel.flush().unwrap();
+        let client_size = channel.read_usize().unwrap();
+        aggregate.set_sizes(client_size, set_size);
+    }
 
+    let mut gb = circuit::join_garbler(&mut channel, &path_delta).unwrap();
+
+    // The partial results of every pass are summed across batches in a reduction tree
+    let sums = (0..aggregate.npasses())
+                .map(|pass| util::reduce_partial_outputs(path, &util::pass_name(category, pass), nthread, A::combine))
+                .collect::<Vec<_>>();
+    let mut outputs = aggregate.output(&mut gb, &sums).unwrap();
+
+    // Both parties add a share of the noise to every output, each calibrated to the full
+    // privacy budget. The budget is split across the outputs that compose.
+    if let Some(mechanism) = &mechanism {
+        let noutputs = aggregate.ncompositions() as f64;
+        let sensitivities = aggregate.sensitivities();
+        for (x, sensitivity) in outputs.iter_mut().zip(sensitivities.iter()){
+            *x = circuit::garbler_noise(&mut gb, x, mechanism, *sensitivity,
+                                        epsilon / noutputs, delta / noutputs).unwrap();
+        }
+    }
 
-        aggregates.append(&mut wires_to_crt(&partial_aggregate));
-        sum_weights.append(&mut wires_to_crt(&partial_sum_weights));
+    // The size of the intersection is the sum of weights of a pass where every weight
+    // is set to one: a pass of the statistic, or the count pass run for this purpose.
+    if min_intersection > 0 {
+        let count = match aggregate.count_pass() {
+            Some(pass) => sums[pass].1.clone(),
+            None => util::reduce_partial_outputs(path, &util::count_pass_name(category), nthread, circuit::add_sums).1,
+        };
+        let reveal = circuit::threshold(&mut gb, &count, min_intersection).unwrap();
+        for x in outputs.iter_mut(){
+            *x = circuit::gate(&mut gb, &reveal, x).unwrap();
+        }
+        gb.output(&reveal).unwrap();
+    }
 
-        path.pop();
+    // In shared mode the receiver only learns the outputs masked by the sender,
+    // who keeps the complement of the masks as its shares. They are masked as well
+    // when the result is only delivered to the sender.
+    let q = outputs[0].composite_modulus();
+    let mut shares = None;
+    if output_mode == OutputMode::Shared || !recipient.receiver_learns() {
+        let mut garbler_shares = Vec::new();
+        for x in outputs.iter_mut(){
+            let (masked, share) = circuit::garbler_share(&mut gb, x).unwrap();
+            *x = masked;
+            garbler_shares.push(share);
+        }
+        shares = Some(garbler_shares);
+    }
+    for x in outputs.iter(){
+        gb.crt_output(x).unwrap();
     }
 
-    let mut psi = Sender::init(&mut channel, &mut rng).unwrap();
-    let _ = psi.join_circuits(&mut aggregates, &mut sum_weights, &path_delta, &mut channel,&mut rng);
+    // The receiver lets the sender know whether the result was suppressed
+    let suppressed = channel.read_bool().unwrap();
+    let outcome = if suppressed {
+        println!("Sender :: Result suppressed, the intersection is smaller than {}", min_intersection);
+        Outcome::Suppressed
+    }else if output_mode == OutputMode::Shared {
+        Outcome::Shares{
+            modulus: q,
+            values: shares.unwrap(),
+        }
+    }else if recipient.sender_learns() {
+        // The receiver forwards the outputs it decoded, which are unmasked with the shares
+        let mut values = output::receive_outputs(&mut channel, outputs.len()).unwrap();
+        if let Some(shares) = shares {
+            values = values.iter()
+                           .zip(shares.iter())
+                           .map(|(value, share)| output::add_mod(*value, *share, q))
+                           .collect();
+        }
+        Outcome::Revealed(output::decode(&values, q))
+    }else{
+        Outcome::Hidden
+    };
 
     println!(
         "Sender :: total Joining threads results time: {} ms",
@@ -68,26 +127,17 @@ fn server_protocol(mut channel: TrackChannel<SymChannel<TcpStream>>, path:&mut P
         "Sender :: total Joining threads results time  (write): {:.2} Mb",
         channel.kilobits_written() / 1000.0
     );
+    outcome
 }
 
-pub fn join_aggregates(path:&mut PathBuf, address: &str, nthreads: usize) {
-    let port_prefix = format!("{}{}", address,":3000");
-    println!("Server listening on {}", port_prefix);
-    let listener = TcpListener::bind(port_prefix).unwrap();
-
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                println!("New connection: {}", stream.peer_addr().unwrap());
-                let channel = TrackChannel::new(SymChannel::new(stream));
-                server_protocol(channel, path, nthreads);
-                return;
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-            }
-        }
-    }
-    drop(listener);
+/// Join the partial results of a round of the statistic. Returns the decoded output values.
+/// `set_size` is the number of records of the server in the category, and `nthread` threads
+/// sum the partial results of every pass.
+pub fn join_aggregates<A: MatchAggregate>(path:&mut PathBuf, parameters: &HashMap<String, String>, aggregate: &mut A,
+                        set_size: usize, address: &str, nthread: usize, category: usize, min_intersection: u128,
+                        last_round: bool) -> Outcome<Vec<i128>> {
+    let stream = util::accept(&format!("{}{}", address,":3000"));
+    let channel = TrackChannel::new(SymChannel::new(stream));
+    server_protocol(channel, path, parameters, aggregate, set_size, nthread, category,
+                    min_intersection, last_round)
 }
@@ -1,4 +1,5 @@
 pub mod run_server;
 pub mod join_aggregates;
 pub mod server_thread;
+pub mod server_coordinator;
 pub mod prepare_files;
@@ -1,14 +1,15 @@
 // Bucketize Data and Seperate it among threads
 use popsicle::psty_payload::{Sender, SenderState};
 
-use scuttlebutt::{AesRng, Block512, TrackChannel, SymChannel};
+use scuttlebutt::{AbstractChannel, AesRng, Block512, TrackChannel, SymChannel};
+use match_compute::util::{self, manifest::Manifest, StateBatches, StatesMode};
 extern crate fancy_garbling;
 use fancy_garbling::Wire;
 
 use std::{
     fs::{File, create_dir_all},
     io::{Write},
-    net::{TcpListener, TcpStream},
+    net::{TcpStream},
     collections::HashMap,
     time::SystemTime,
     path::PathBuf,
@@ -25,11 +26,12 @@ pub fn generate_deltas() -> HashMap<u16, Wire> {
     deltas
 }
 
-fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path: &mut PathBuf, nthread: usize,
-                    ids: &[Vec<u8>], payloads: &[Block512], payload_size: usize){
-    let start = SystemTime::now();
+/// The deltas are shared by every pass of the computation, so that the partial
+/// results of different passes can be combined in the same join circuit.
+pub fn write_deltas(path: &mut PathBuf){
+    let path_str = path.clone().into_os_string().into_string().unwrap();
+    let _ = create_dir_all(path_str);
 
-    let mut rng = AesRng::new();
     let deltas = generate_deltas();
     path.push("delta.txt");
 
@@ -39,6 +41,34 @@ fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path: &mut P
 
     let deltas_json = serde_json::to_string(&deltas).unwrap();
     file_deltas.write(deltas_json.as_bytes()).unwrap();
+}
+
+fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path: &mut PathBuf, pass: &str, batch_size: usize,
+                    states_mode: &StatesMode, round: usize, checkpoint: Option<Manifest>,
+                    ids: &[Vec<u8>], payloads: &[Block512], payload_size: usize)
+                    -> StateBatches<SenderState>{
+    let start = SystemTime::now();
+
+    // The pass is resumed when both parties have a checkpoint of it. Only the batches that
+    // both parties computed are kept, the others are computed again by both.
+    let client_checkpoint = stream.read_bool().unwrap();
+    let resumed = client_checkpoint && checkpoint.is_some();
+    stream.write_bool(resumed).unwrap();
+    stream.flush().unwrap();
+    if resumed {
+        let client_manifest = Manifest::receive(&mut stream).unwrap();
+        let manifest = checkpoint.unwrap().agree(&client_manifest);
+        manifest.send(&mut stream).unwrap();
+        println!("Sender :: Resuming {}, {} batches left", pass, manifest.remaining().len());
+
+        let batches = StateBatches::resume(manifest);
+        path.push(pass);
+        batches.write_manifest(path);
+        path.pop();
+        return batches;
+    }
+
+    let mut rng = AesRng::new();
 
     let mut psi = Sender::init(&mut stream, &mut rng).unwrap();
 
@@ -48,32 +78,34 @@ fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path: &mut P
                     &ids, &payloads, payload_size, &mut stream, &mut rng
                 ).unwrap();
 
-    let megabin_per_thread = ((megabins.nmegabins as f32)/(nthread as f32)).ceil() as usize;
+    // After bucketization, the megabins are grouped into batches that the threads pull from
+    // a shared queue. The client groups the megabins it sent out the same way.
+    let batches = StateBatches::new(megabins.states, batch_size, round);
+    assert!(!batches.is_empty(), "no megabins to compute, the client set is empty");
 
-
-    // After bucketization, data is divided accross threads at this level according to the
-    // megabin size that the client sends out
-    let states_per_thread:Vec<&[SenderState]> = megabins.states.chunks(megabin_per_thread).collect();
-
-    // Create files and folders with the data that each thread should handle.
-    for i in 0 ..nthread{
-        let mut thread_path = "thread".to_owned();
-        thread_path.push_str(&i.to_string());
-        path.push(thread_path);
+    // Create the folders of every batch, where the partial results are written.
+    // The states are only written there when they are checkpointed on disk.
+    path.push(pass);
+    for batch in 0..batches.len(){
+        path.push(util::batch_name(batch));
 
         let path_str = path.clone().into_os_string().into_string().unwrap();
         let _ = create_dir_all(path_str);
 
-        path.push("states.txt");
-        let path_str = path.clone().into_os_string().into_string().unwrap();
-        let mut file_states = File::create(path_str).unwrap();
-        path.pop();
+        if *states_mode == StatesMode::Files {
+            path.push("states.txt");
+            let path_str = path.clone().into_os_string().into_string().unwrap();
+            let mut file_states = File::create(path_str).unwrap();
+            path.pop();
 
-        let state_json = bincode::serialize(&states_per_thread[i]).unwrap();
-        file_states.write(&state_json).unwrap();
+            let state_json = bincode::serialize(&batches.take(batch).unwrap()).unwrap();
+            file_states.write(&state_json).unwrap();
+        }
 
         path.pop();
     }
+    batches.write_manifest(path);
+    path.pop();
 
     println!(
         "Sender :: Bucketization time: {} ms",
@@ -87,28 +119,14 @@ fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path: &mut P
         "Sender :: Bucketization time  (write): {:.2} Mb",
         stream.kilobits_written() / 1000.0
     );
-
+    batches
 }
 
-pub fn prepare_files(path: &mut PathBuf, address: &str, nthread: usize,
-    ids: &[Vec<u8>], payloads: &[Block512], payload_size: usize) {
-    let address = format!("{}{}", address,":3000");
-    println!("Server listening on {}", address);
-    let listener = TcpListener::bind(address).unwrap();
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                println!("New connection: {}", stream.peer_addr().unwrap());
-                    let channel = TrackChannel::new(SymChannel::new(stream));
-                    server_protocol(channel, path, nthread, ids, payloads, payload_size);
-                    return;
-
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-            }
-        }
-    }
-    drop(listener);
+/// Bucketize the data of `pass` with the client and split its megabins into batches.
+/// Returns the batches, whose states are kept in memory unless they were written to disk.
+pub fn prepare_files(path: &mut PathBuf, pass: &str, address: &str, batch_size: usize, states_mode: &StatesMode,
+    round: usize, checkpoint: Option<Manifest>, ids: &[Vec<u8>], payloads: &[Block512], payload_size: usize) -> StateBatches<SenderState> {
+    let stream = util::accept(&format!("{}{}", address,":3000"));
+    let channel = TrackChannel::new(SymChannel::new(stream));
+    server_protocol(channel, path, pass, batch_size, states_mode, round, checkpoint, ids, payloads, payload_size)
 }
@@ -1,21 +1,40 @@
-use match_compute::util;
+use match_compute::{
+    aggregate::MatchAggregate,
+    output::{self, Outcome, OutputMode},
+    util::{self, tuning::{self, Resources}, StateBatches, StatesMode},
+};
 
 use crate::utils::{
-    prepare_files::prepare_files,
+    prepare_files::{prepare_files, write_deltas},
     server_thread::server_thread,
+    server_coordinator::server_coordinator,
     join_aggregates::join_aggregates,
 };
 
+use popsicle::psty_payload::SenderState;
+use scuttlebutt::{Block, Block512, SymChannel};
 use std::{
+    panic,
+    sync::{mpsc, Arc},
     thread,
+    path::PathBuf,
 };
-pub fn run_server(set_size: usize, id_size: usize, max_payload:u64, payload_size: usize, fake_data: bool){
+pub fn run_server<A: MatchAggregate>(aggregate: &A, set_size: usize, id_size: usize, max_payload:u64,
+                payload_size: usize, fake_data: bool) -> Vec<Outcome<A::Output>>{
 
     let mut path = util::get_path();
     let parameters = util::parse_config(&mut path.clone());
     let (address, server_path, nthread, id_position, payload_position) =
                                         util::get_config_sever(&parameters);
 
+    let (ncategories, category_position) = util::get_config_group_by(&parameters);
+    let batch_size = util::get_config_batch_size(&parameters);
+    let states_mode = util::get_config_states_mode(&parameters);
+    let resume = util::get_config_resume(&parameters);
+    let (_, workers) = util::get_config_workers(&parameters);
+    let worker_key = util::get_config_worker_key(&parameters);
+    assert!(workers.is_empty() || worker_key.is_some(), "the coordinator needs a worker_key to speak to its workers");
+
     let(ids, payloads) = if fake_data == true {
             // The ids & payloads are generated at random
             util::generate_dummy_data(set_size, id_size, max_payload)
@@ -25,27 +44,162 @@ pub fn run_server(set_size: usize, id_size: usize, max_payload:u64, payload_size
             util::parse_files(id_position, payload_position, &server_path)
         };
 
-   // Bucketize the data and split into megabins that are distributed among threads
+    // Every row belongs to the single category 0 unless a group-by is requested
+    let categories = if ncategories == 1 {
+            vec![0; ids.len()]
+        }else if fake_data == true {
+            util::generate_dummy_categories(ids.len(), ncategories)
+        }else{
+            util::parse_column(category_position, &server_path)
+        };
+    assert!(categories.iter().all(|c| (*c as usize) < ncategories),
+            "category outside of the domain agreed in the configuration");
+
+    let min_intersection = util::get_config_min_intersection(&parameters);
+
+    // The columns the statistic is computed from. The aggregate was checked
+    // statically for intersections of at most set_size records.
+    assert!(ids.len() <= set_size, "the server has more than set_size records");
+    // The ids are shared with the thread that bucketizes the passes
+    let ids = Arc::new(ids);
+    let specs = util::get_config_columns(&parameters, "server_columns");
+    let table = util::read_columns(&specs, &payloads, max_payload, &server_path, fake_data);
+
     path.push("bin/parallel-server/data");
-    prepare_files(&mut path, &address, nthread, &ids, &payloads, payload_size);
+    // A resumed run keeps the deltas the checkpointed partial results were garbled with
+    path.push("delta.txt");
+    let has_deltas = path.exists();
+    path.pop();
+    if !resume || !has_deltas {
+        write_deltas(&mut path);
+    }
 
-    // Each thread handles its own megabins and speaks to the appropriate other party thread
-    // via a dedicated port. The partial results of this computation are garbled and
-    // stored into appropriate files. They are handled later to produce the correct output.
-    let mut handle = Vec::new();
-    for i in 0..nthread {
-        let mut path_thread = path.clone();
-        let address_thread = address.clone();
-       handle.push(thread::spawn(move || {
-           server_thread(&mut path_thread, &address_thread, i, payload_size);
-       }));
-   }
-   for thread in handle {
-        let _ = thread.join();
+    // The parties agree on the number of threads and the size of the megabins before the first pass.
+    // The server offers the agreed maximum set size rather than its own.
+    let stream = util::accept(&format!("{}{}", address,":3000"));
+    let resources = Resources::local(nthread, util::get_config_memory_budget(&parameters), set_size);
+    let tuning = tuning::agree_server(&mut SymChannel::new(stream), &resources, payload_size, batch_size).unwrap();
+    tuning.log("Sender");
+    let nthread = tuning.nthread;
+
+    let mut outcomes = Vec::new();
+    // The computation is run once per category on the columns masked to that category,
+    // so that each run produces the acc/sum_weights pairs of a single category.
+    for category in 0..ncategories {
+        println!("Sender :: Category {}", category);
+        // Statistics computed over several rounds start afresh for every category
+        let mut aggregate = aggregate.clone();
+        let nrounds = aggregate.nrounds();
+        let mut outcome = Outcome::Hidden;
+        for round in 0..nrounds {
+            // The passes that do not depend on the previous rounds are only computed in the first
+            let mut passes = (0..aggregate.npasses())
+                                .filter(|pass| round == 0 || aggregate.recompute(*pass))
+                                .map(|pass| {
+                                    let column = util::int_vec_block512(aggregate.server_column(pass, &table));
+                                    (util::pass_name(category, pass), util::mask_payloads(&column, &categories, category as u64))
+                                }).collect::<Vec<(String, Vec<Block512>)>>();
+
+            // When a minimum intersection size is required, an additional pass with unit weights
+            // counts the matched records of the category, unless a pass of the statistic does
+            if min_intersection > 0 && round == 0 && aggregate.count_pass().is_none() {
+                let ones = util::int_vec_block512(vec![1; ids.len()]);
+                passes.push((util::count_pass_name(category), util::mask_payloads(&ones, &categories, category as u64)));
+            }
+            run_passes(&mut path, passes, &address, nthread, batch_size, &states_mode, round, resume,
+                       &workers, worker_key, &ids, payload_size);
+
+            // The partial results are joined and the output is produced
+            let category_size = categories.iter().filter(|c| **c == category as u64).count();
+            let values = join_aggregates(&mut path, &parameters, &mut aggregate, category_size, &address,
+                                        nthread, category, min_intersection, round + 1 == nrounds);
+            match values {
+                Outcome::Revealed(values) if round + 1 < nrounds => aggregate.next_round(&values),
+                values => {
+                    outcome = values.map(|values| aggregate.finalize(&values));
+                    break;
+                }
+            }
+        }
+        println!("{}: {}", aggregate.name(), outcome);
+        outcomes.push(outcome);
     }
 
-    // The partial results are joined and the output is produced
-    join_aggregates(&mut path, &address, nthread);
+    // The statistics are written to result.txt, or the shares to share.txt
+    let output_mode = util::get_config_output_mode(&parameters);
+    if output_mode == OutputMode::Shared || util::get_config_output_recipient(&parameters).sender_learns() {
+        output::write_outcomes(&mut path, &output_mode, &aggregate.name(), &outcomes);
+    }
 
     println!("Experiments done !");
+    outcomes
+}
+
+/// Run the passes of a round. The passes are bucketized one after the other by a separate thread,
+/// while the batches of the pass bucketized last are computed, so that the bucketization of a pass
+/// overlaps the computation of the previous one. The thread bucketizes at most one pass ahead.
+fn run_passes(path: &mut PathBuf, passes: Vec<(String, Vec<Block512>)>, address: &str, nthread: usize,
+            batch_size: usize, states_mode: &StatesMode, round: usize, resume: bool, workers: &[String],
+            worker_key: Option<Block>, ids: &Arc<Vec<Vec<u8>>>, payload_size: usize){
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let bucketization = {
+        let mut path = path.clone();
+        let address = address.to_owned();
+        let states_mode = states_mode.clone();
+        let ids = Arc::clone(ids);
+        thread::spawn(move || {
+            for (pass, payloads) in passes {
+                // A resumed run picks the pass up from its manifest when the client can as well
+                let checkpoint = if resume { util::read_checkpoint(&mut path, &pass, round, &states_mode) } else { None };
+
+                // Bucketize the data and split into batches of megabins that are distributed among threads
+                let batches = prepare_files(&mut path, &pass, &address, batch_size, &states_mode, round, checkpoint,
+                                            &ids, &payloads, payload_size);
+                sender.send((pass, batches)).unwrap();
+            }
+        })
+    };
+    for (pass, batches) in receiver {
+        run_pass(path, &pass, address, nthread, workers, worker_key, payload_size, Arc::new(batches));
+    }
+    bucketization.join().unwrap();
+}
+
+fn run_pass(path: &mut PathBuf, pass: &str, address: &str, nthread: usize, workers: &[String],
+            worker_key: Option<Block>, payload_size: usize,
+            batches: Arc<StateBatches<SenderState>>){
+    // Each thread pulls batches of megabins from a shared queue and speaks to the appropriate
+    // other party thread via a dedicated port. The partial results of this computation are garbled
+    // and stored into the folder of their batch. They are handled later to produce the correct output.
+    // When there are fewer batches left than threads, the threads left without a batch are not run.
+    // With worker processes on other machines, the threads hand out the batches to the workers instead.
+    let mut handle = Vec::new();
+    if workers.is_empty() {
+        for i in 0..util::nworkers(nthread, batches.nremaining()) {
+            let mut path_thread = path.clone();
+            let pass_thread = pass.to_owned();
+            let address_thread = address.to_owned();
+            let batches_thread = Arc::clone(&batches);
+            handle.push(thread::spawn(move || {
+                server_thread(&mut path_thread, &pass_thread, &address_thread, i, payload_size, batches_thread);
+            }));
+        }
+    }else{
+        for worker in workers.iter().take(util::nworkers(workers.len(), batches.nremaining())) {
+            let mut path_thread = path.clone();
+            let pass_thread = pass.to_owned();
+            let worker = worker.clone();
+            let batches_thread = Arc::clone(&batches);
+            handle.push(thread::spawn(move || {
+                server_coordinator(&mut path_thread, &pass_thread, &worker, payload_size, &batches_thread,
+                                   worker_key.unwrap()).unwrap();
+            }));
+        }
+    }
+    // A thread or a coordinator that panicked fails the pass
+    for thread in handle {
+        if let Err(e) = thread.join() {
+            panic::resume_unwind(e);
+        }
+    }
 }
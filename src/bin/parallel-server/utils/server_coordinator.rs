@@ -0,0 +1,82 @@
+// Hands out the batches of megabins to a server worker process on another machine
+use popsicle::psty_payload::SenderState;
+
+use scuttlebutt::{AbstractChannel, Block, TrackChannel, SymChannel};
+
+use match_compute::util::{self, format, remote::{self, Job, SealedChannel}, StateBatches};
+use std::{
+    fs::read,
+    net::TcpStream,
+    time::SystemTime,
+    path::PathBuf,
+    io::Error,
+};
+use bincode;
+
+use crate::utils::server_thread::read_states;
+
+fn coordinator_protocol(mut channel: TrackChannel<SealedChannel<SymChannel<TcpStream>>>, path: &mut PathBuf, pass: &str,
+                        worker: &str, payload_size: usize, batches: &StateBatches<SenderState>){
+    let start = SystemTime::now();
+
+    // The worker garbles the partial results with the deltas of the coordinator,
+    // so that they can be combined in the join circuit.
+    path.push("delta.txt");
+    let deltas = read(&path).unwrap();
+    path.pop();
+    Job{
+        pass: pass.to_owned(),
+        payload_size,
+        peer: String::new(),
+        deltas,
+    }.send(&mut channel).unwrap();
+
+    // The states of every batch pulled from the queue are shipped to the worker,
+    // which sends back the garbled partial results.
+    path.push(pass);
+    while let Some(batch) = batches.pop() {
+        path.push(util::batch_name(batch));
+        let states = batches.take(batch).unwrap_or_else(|| read_states(path));
+        channel.write_bool(true).unwrap();
+        channel.write_usize(batch).unwrap();
+        remote::send_bytes(&mut channel, &bincode::serialize(&states).unwrap()).unwrap();
+
+        let (_, acc) = format::decode_bundles(&remote::receive_bytes(&mut channel).unwrap()).unwrap();
+        let (_, sum_weights) = format::decode_bundles(&remote::receive_bytes(&mut channel).unwrap()).unwrap();
+        util::write_partial_outputs(path, &acc, &sum_weights);
+        path.pop();
+        batches.finish(batch, path);
+    }
+    channel.write_bool(false).unwrap();
+    channel.flush().unwrap();
+    path.pop();
+    let (read, written) = remote::receive_communication(&mut channel).unwrap();
+
+    println!(
+        "Sender Worker {} :: total computation time: {} ms", worker,
+        start.elapsed().unwrap().as_millis()
+    );
+    println!(
+        "Sender Worker {} :: total computation communication (read): {:.2} Mb", worker,
+        read / 1000.0
+    );
+    println!(
+        "Sender Worker {} :: total computation communication (write): {:.2} Mb", worker,
+        written / 1000.0
+    );
+}
+
+pub fn server_coordinator(path: &mut PathBuf, pass: &str, worker: &str, payload_size: usize,
+                        batches: &StateBatches<SenderState>, key: Block) -> Result<(), Error>{
+    match TcpStream::connect(worker) {
+        Ok(stream) => {
+            let channel = TrackChannel::new(SealedChannel::coordinator(SymChannel::new(stream), key)?);
+            coordinator_protocol(channel, path, pass, worker, payload_size, batches);
+            Ok(())
+        },
+        Err(e) => {
+            println!("Failed to connect to worker {}: {}", worker, e);
+            Err(e)
+        }
+    }
+}
@@ -4,30 +4,35 @@ use popsicle::psty_utils::psty_large::{
     SenderMegabins,
 };
 
-use scuttlebutt::{AesRng, TrackChannel, SymChannel};
-
-use fancy_garbling::{
-    CrtBundle,
-    Wire,
-};
+use scuttlebutt::{AbstractChannel, AesRng, TrackChannel, SymChannel};
 
+use match_compute::util::{self, StateBatches};
 use std::{
     fs::{File},
-    io::{Write, Read},
-    net::{TcpListener, TcpStream},
+    io::{Read},
+    net::{TcpStream},
+    sync::Arc,
     time::SystemTime,
     path::PathBuf,
 };
-use serde_json;
 use bincode;
 
-fn crt_to_wires(v: &[CrtBundle<Wire>])-> Vec<Vec<Wire>>{
-    v.into_iter()
-     .map(|c| c.wires().to_vec()).collect()
+/// Read the states of a batch back from its folder.
+pub fn read_states(path:&mut PathBuf) -> Vec<SenderState> {
+    path.push("states.txt");
+    let path_str = path.clone().into_os_string().into_string().unwrap();
+    let mut file_states = File::open(path_str).unwrap();
+    path.pop();
+
+    let mut buff= Vec::new();
+
+    file_states.read_to_end(&mut buff).unwrap();
+
+    bincode::deserialize(&mut buff).unwrap()
 }
 
-fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path:&mut PathBuf,
-            thread_id: usize, payload_size: usize) {
+fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path:&mut PathBuf, pass: &str,
+            thread_id: usize, payload_size: usize, batches: &StateBatches<SenderState>) {
     let start = SystemTime::now();
     println!("Sender Thread {} Starting computation", thread_id);
 
@@ -37,28 +42,33 @@ fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path:&mut Pa
     let path_delta = path.clone().into_os_string().into_string().unwrap();
     path.pop();
 
-    let mut thread_path = "thread".to_owned();
-    thread_path.push_str(&thread_id.to_string());
-    path.push(thread_path);
-
-    path.push("states.txt");
-    let path_str = path.clone().into_os_string().into_string().unwrap();
-    let mut file_states = File::open(path_str).unwrap();
-    path.pop();
-
-    let mut buff= Vec::new();
-
-    file_states.read_to_end(&mut buff).unwrap();
-
-    let states: Vec<SenderState> = bincode::deserialize(&mut buff).unwrap();
-    let nmegabins = states.len();
-    let mut megabins = SenderMegabins{
-        states,
-        nmegabins,
-    };
     let mut psi = Sender::init(&mut stream, &mut rng).unwrap();
     let p =  fancy_garbling::util::primes_with_width(payload_size as u32).len() + 1;
-    let (acc, sum_weights) = psi.compute_circuit(p, payload_size, &mut megabins, &path_delta, &mut stream, &mut rng).unwrap();
+
+    // The thread pulls batches from the queue until it is empty, and tells the client
+    // thread it is paired with which batch it took before computing it.
+    path.push(pass);
+    while let Some(batch) = batches.pop() {
+        stream.write_bool(true).unwrap();
+        stream.write_usize(batch).unwrap();
+        stream.flush().unwrap();
+
+        path.push(util::batch_name(batch));
+        // The states are read back from the folder of the batch when they were written to disk
+        let states = batches.take(batch).unwrap_or_else(|| read_states(path));
+        let nmegabins = states.len();
+        let mut megabins = SenderMegabins{
+            states,
+            nmegabins,
+        };
+        let (acc, sum_weights) = psi.compute_circuit(p, payload_size, &mut megabins, &path_delta, &mut stream, &mut rng).unwrap();
+        util::write_partial_outputs(path, &acc, &sum_weights);
+        path.pop();
+        batches.finish(batch, path);
+    }
+    stream.write_bool(false).unwrap();
+    stream.flush().unwrap();
+    path.pop();
 
     println!(
         "Sender Thread {} :: total circuit building & computation time: {} ms", thread_id,
@@ -72,41 +82,11 @@ fn server_protocol(mut stream: TrackChannel<SymChannel<TcpStream>>, path:&mut Pa
         "Sender Thread {} :: total circuit building & computation communication (write): {:.2} Mb",thread_id,
         stream.kilobits_written() / 1000.0
     );
-    path.push("output_aggregate.txt");
-    let path_str = path.clone().into_os_string().into_string().unwrap();
-    let mut file_aggregate = File::create(path_str).unwrap();
-    path.pop();
-
-    path.push("output_sum_weights.txt");
-    let path_str = path.clone().into_os_string().into_string().unwrap();
-    let mut file_sum_weights = File::create(path_str).unwrap();
-    path.pop();
-
-    let aggregate_json = serde_json::to_string(&crt_to_wires(&acc)).unwrap();
-    let sum_weights_json = serde_json::to_string(&crt_to_wires(&sum_weights)).unwrap();
-
-    file_aggregate.write(aggregate_json.as_bytes()).unwrap();
-    file_sum_weights.write(sum_weights_json.as_bytes()).unwrap();
 }
 
-pub fn server_thread(path:&mut PathBuf, address: &str, thread_id: usize, payload_size: usize) {
-    let port_prefix = format!("{}{}", address,":300");
-    let port = format!("{}{}", port_prefix, thread_id.to_string());
-    println!("Server listening on {}", port);
-
-    let listener = TcpListener::bind(port).unwrap();
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                println!("New connection: {}", stream.peer_addr().unwrap());
-                let channel = TrackChannel::new(SymChannel::new(stream));
-                server_protocol(channel, path, thread_id, payload_size);
-                return;
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-            }
-        }
-    }
-    drop(listener);
+pub fn server_thread(path:&mut PathBuf, pass: &str, address: &str, thread_id: usize, payload_size: usize,
+                    batches: Arc<StateBatches<SenderState>>) {
+    let stream = util::accept(&util::thread_address(address, thread_id));
+    let channel = TrackChannel::new(SymChannel::new(stream));
+    server_protocol(channel, path, pass, thread_id, payload_size, &batches);
 }
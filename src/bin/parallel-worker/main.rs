@@ -0,0 +1,34 @@
+// A worker process computing batches of megabins on behalf of the coordinator of a party,
+// the parallel-server or parallel-client run with `server_workers` and `client_workers`.
+// It handles the jobs of the coordinator one pass after the other, until it is stopped.
+//
+// Usage: parallel-worker <server|client> <control address>
+mod utils;
+use match_compute::util;
+use crate::utils::{client_worker::client_worker, server_worker::server_worker};
+
+use std::env;
+
+pub fn main(){
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 || (args[1] != "server" && args[1] != "client") {
+        println!("Usage: {} <server|client> <control address>", args[0]);
+        return;
+    }
+
+    // Every worker keeps the deltas of its jobs in a folder named after its control address
+    let mut path = util::get_path();
+    path.push("bin/parallel-worker/data");
+    path.push(format!("worker_{}", args[2].replace(|c: char| !c.is_ascii_alphanumeric(), "_")));
+
+    // The coordinator of the party speaks to the worker with the key they share
+    let parameters = util::parse_config(&mut util::get_path());
+    let key = util::get_config_worker_key(&parameters).expect("a worker needs the worker_key of its party");
+
+    if args[1] == "server" {
+        server_worker(&mut path, &args[2], key);
+    }else{
+        // The client worker waits for the server worker it pairs up with as long as the client does
+        client_worker(&args[2], util::get_config_connect_timeout(&parameters), key);
+    }
+}
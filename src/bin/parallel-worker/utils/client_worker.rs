@@ -0,0 +1,81 @@
+// Computation of the batches requested from the client coordinator, with the server worker paired up
+use popsicle::psty_payload::{Receiver, ReceiverState};
+use popsicle::psty_utils::psty_large::{
+    ReceiverMegabins,
+};
+
+use scuttlebutt::{AbstractChannel, AesRng, Block, TrackChannel, SymChannel};
+
+use match_compute::util::{self, format, remote::{self, Job, SealedChannel}};
+use std::{
+    net::{TcpListener, TcpStream},
+    time::{Duration, SystemTime},
+};
+use bincode;
+
+fn client_job(mut control: TrackChannel<SealedChannel<SymChannel<TcpStream>>>, address: &str, timeout: Duration){
+    let start = SystemTime::now();
+    let job = Job::receive(&mut control).unwrap();
+    println!("Receiver Worker {} Starting {}", address, job.pass);
+
+    // The server worker starts listening once it received its job
+    let stream = util::connect(&job.peer, timeout).unwrap();
+    let mut channel = TrackChannel::new(SymChannel::new(stream));
+
+    let mut rng = AesRng::new();
+    let mut psi = Receiver::init(&mut channel, &mut rng).unwrap();
+    let p =  fancy_garbling::util::primes_with_width(job.payload_size as u32).len() + 1;
+
+    // The server worker hands out the batches its coordinator ships,
+    // whose states are requested from the client coordinator.
+    while channel.read_bool().unwrap() {
+        let batch = channel.read_usize().unwrap();
+        control.write_bool(true).unwrap();
+        control.write_usize(batch).unwrap();
+        control.flush().unwrap();
+        let states: Vec<ReceiverState> = bincode::deserialize(&remote::receive_bytes(&mut control).unwrap()).unwrap();
+
+        let nmegabins = states.len();
+        let mut megabins = ReceiverMegabins{
+            states,
+            nmegabins,
+        };
+        let (acc, sum_weights) = psi.compute_circuit(p, job.payload_size, &mut megabins, &mut channel, &mut rng).unwrap();
+        remote::send_bytes(&mut control, &format::encode_bundles(&acc)).unwrap();
+        remote::send_bytes(&mut control, &format::encode_bundles(&sum_weights)).unwrap();
+    }
+    control.write_bool(false).unwrap();
+    remote::send_communication(&mut control, channel.kilobits_read(), channel.kilobits_written()).unwrap();
+
+    println!(
+        "Receiver Worker {} :: total circuit building & computation time: {} ms", address,
+        start.elapsed().unwrap().as_millis()
+    );
+    println!(
+        "Receiver Worker {} :: total circuit building & computation communication (read): {:.2} Mb", address,
+        channel.kilobits_read() / 1000.0
+    );
+    println!(
+        "Receiver Worker {} :: total circuit building & computation communication (write): {:.2} Mb", address,
+        channel.kilobits_written() / 1000.0
+    );
+}
+
+pub fn client_worker(address: &str, timeout: Duration, key: Block){
+    println!("Worker listening on {}", address);
+    let listener = TcpListener::bind(address).unwrap();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                println!("New job from: {}", stream.peer_addr().unwrap());
+                match SealedChannel::worker(SymChannel::new(stream), key) {
+                    Ok(channel) => client_job(TrackChannel::new(channel), address, timeout),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+            }
+        }
+    }
+}
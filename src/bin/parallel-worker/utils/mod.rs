@@ -0,0 +1,2 @@
+pub mod client_worker;
+pub mod server_worker;
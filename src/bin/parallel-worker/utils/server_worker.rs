@@ -0,0 +1,116 @@
+// Computation of the batches shipped by the server coordinator, with the client worker paired up
+use popsicle::psty_payload::{Sender, SenderState};
+use popsicle::psty_utils::psty_large::{
+    SenderMegabins,
+};
+
+use scuttlebutt::{AbstractChannel, AesRng, Block, TrackChannel, SymChannel};
+
+use match_compute::util::{format, remote::{self, Job, SealedChannel}};
+use std::{
+    fs::{create_dir_all, remove_file, OpenOptions},
+    io::Write,
+    net::{TcpListener, TcpStream},
+    os::unix::fs::OpenOptionsExt,
+    time::SystemTime,
+    path::PathBuf,
+};
+use bincode;
+
+// Popsicle reads the deltas the partial results are garbled with from a file. They are written
+// readable by the worker only, and removed as soon as the job ends, even when it fails.
+struct DeltaFile {
+    path: String,
+}
+
+impl DeltaFile {
+    fn create(path: String, deltas: &[u8]) -> DeltaFile {
+        let _ = remove_file(&path);
+        let file = DeltaFile{ path };
+        let mut delta = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&file.path).unwrap();
+        delta.write_all(deltas).unwrap();
+        file
+    }
+}
+
+impl Drop for DeltaFile {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+fn server_job(mut control: TrackChannel<SealedChannel<SymChannel<TcpStream>>>, path: &mut PathBuf, address: &str){
+    let start = SystemTime::now();
+    let job = Job::receive(&mut control).unwrap();
+    println!("Sender Worker {} Starting {}", address, job.pass);
+
+    let path_str = path.clone().into_os_string().into_string().unwrap();
+    let _ = create_dir_all(path_str);
+    path.push("delta.txt");
+    let delta = DeltaFile::create(path.clone().into_os_string().into_string().unwrap(), &job.deltas);
+    path.pop();
+
+    // The client worker paired up with this worker connects to its pairing port
+    let listener = TcpListener::bind(remote::pairing_address(address)).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    println!("New connection: {}", stream.peer_addr().unwrap());
+    let mut stream = TrackChannel::new(SymChannel::new(stream));
+
+    let mut rng = AesRng::new();
+    let mut psi = Sender::init(&mut stream, &mut rng).unwrap();
+    let p =  fancy_garbling::util::primes_with_width(job.payload_size as u32).len() + 1;
+
+    // The coordinator ships the states of the batches it pulls from its queue, and the client
+    // worker is told which batch was taken before computing it.
+    while control.read_bool().unwrap() {
+        let batch = control.read_usize().unwrap();
+        let states: Vec<SenderState> = bincode::deserialize(&remote::receive_bytes(&mut control).unwrap()).unwrap();
+        stream.write_bool(true).unwrap();
+        stream.write_usize(batch).unwrap();
+        stream.flush().unwrap();
+
+        let nmegabins = states.len();
+        let mut megabins = SenderMegabins{
+            states,
+            nmegabins,
+        };
+        let (acc, sum_weights) = psi.compute_circuit(p, job.payload_size, &mut megabins, &delta.path, &mut stream, &mut rng).unwrap();
+        remote::send_bytes(&mut control, &format::encode_bundles(&acc)).unwrap();
+        remote::send_bytes(&mut control, &format::encode_bundles(&sum_weights)).unwrap();
+    }
+    stream.write_bool(false).unwrap();
+    stream.flush().unwrap();
+    remote::send_communication(&mut control, stream.kilobits_read(), stream.kilobits_written()).unwrap();
+
+    println!(
+        "Sender Worker {} :: total circuit building & computation time: {} ms", address,
+        start.elapsed().unwrap().as_millis()
+    );
+    println!(
+        "Sender Worker {} :: total circuit building & computation communication (read): {:.2} Mb", address,
+        stream.kilobits_read() / 1000.0
+    );
+    println!(
+        "Sender Worker {} :: total circuit building & computation communication (write): {:.2} Mb", address,
+        stream.kilobits_written() / 1000.0
+    );
+}
+
+pub fn server_worker(path: &mut PathBuf, address: &str, key: Block){
+    println!("Worker listening on {}", address);
+    let listener = TcpListener::bind(address).unwrap();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                println!("New job from: {}", stream.peer_addr().unwrap());
+                match SealedChannel::worker(SymChannel::new(stream), key) {
+                    Ok(channel) => server_job(TrackChannel::new(channel), path, address),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+            }
+        }
+    }
+}
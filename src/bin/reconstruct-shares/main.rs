@@ -0,0 +1,49 @@
+// Reconstruct the statistics from the share files written by both parties
+// when the computation is run with `output_mode: shared`.
+//
+// Usage: reconstruct-shares <client share.txt> <server share.txt>
+use match_compute::{
+    aggregate::{correlation::Moment, MatchAggregate, Statistic},
+    output::{self, Outcome},
+    util,
+};
+
+use std::env;
+
+pub fn main(){
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        println!("Usage: {} <client share file> <server share file>", args[0]);
+        return;
+    }
+
+    let path = util::get_path();
+    let parameters = util::parse_config(&mut path.clone());
+    match util::get_config_statistic(&parameters) {
+        Statistic::Expression => reconstruct(&util::get_config_aggregate(&parameters), &args[1], &args[2]),
+        Statistic::Histogram => reconstruct(&util::get_config_histogram(&parameters), &args[1], &args[2]),
+        Statistic::Covariance => reconstruct(&util::get_config_correlation(&parameters, Moment::Covariance),
+                                            &args[1], &args[2]),
+        Statistic::Correlation => reconstruct(&util::get_config_correlation(&parameters, Moment::Correlation),
+                                            &args[1], &args[2]),
+        Statistic::Regression => reconstruct(&util::get_config_regression(&parameters), &args[1], &args[2]),
+        Statistic::Quantile => panic!("the quantile is always revealed to both parties"),
+        Statistic::Membership => panic!("the matched rows are always revealed to the client"),
+        Statistic::Lookup => panic!("the labels are always revealed to the client"),
+        Statistic::Jaccard => panic!("the overlap is always revealed"),
+    }
+}
+
+fn reconstruct<A: MatchAggregate>(aggregate: &A, path_client: &str, path_server: &str){
+    let shares_client = output::read_shares::<A::Output>(path_client);
+    let shares_server = output::read_shares::<A::Output>(path_server);
+    assert_eq!(shares_client.len(), shares_server.len(), "the share files do not have the same number of categories");
+
+    for (category, (share_client, share_server)) in shares_client.iter().zip(shares_server.iter()).enumerate(){
+        let outcome = match output::reconstruct(share_client, share_server) {
+            Some((values, q)) => Outcome::Revealed(aggregate.finalize(&output::decode(&values, q))),
+            None => Outcome::Suppressed,
+        };
+        println!("Category {} :: {}: {}", category, aggregate.name(), outcome);
+    }
+}
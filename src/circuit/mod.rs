@@ -0,0 +1,134 @@
+// Gadgets used to join the partial results computed by every thread.
+// They are generic over `Fancy` so that the garbler and the evaluator
+// build the exact same circuit.
+use fancy_garbling::{
+    errors::TwopacError,
+    twopac::semihonest::{Evaluator, Garbler},
+    CrtBundle,
+    CrtGadgets,
+    BundleGadgets,
+    HasModulus,
+    Fancy,
+    FancyInput,
+    Wire,
+};
+use ocelot::ot::{AlszReceiver as OtReceiver, AlszSender as OtSender};
+use scuttlebutt::{AbstractChannel, AesRng};
+
+use rand::Rng;
+
+use crate::{
+    aggregate::PassSums,
+    noise::{self, Mechanism},
+    output,
+};
+
+/// Add two garbled CRT bundles outside of the join circuit. Addition gates are free:
+/// the garbler and the evaluator each add the labels of their wires, without talking
+/// to each other, so the partial results can be summed by several threads beforehand.
+pub fn add_bundles(x: &CrtBundle<Wire>, y: &CrtBundle<Wire>) -> CrtBundle<Wire>{
+    assert!(x.wires().iter().map(|w| w.modulus()).eq(y.wires().iter().map(|w| w.modulus())),
+            "cannot add partial results with different moduli");
+    CrtBundle::new(x.wires().iter().zip(y.wires().iter()).map(|(x, y)| x.plus(y)).collect())
+}
+
+/// Add the partial `(acc, sum_weights)` of a pass computed by two threads.
+pub fn add_sums(x: &PassSums<Wire>, y: &PassSums<Wire>) -> PassSums<Wire>{
+    (add_bundles(&x.0, &y.0), add_bundles(&x.1, &y.1))
+}
+
+/// Combine the partial results in a reduction tree, pairwise stage after stage.
+/// Returns `None` when there are none.
+pub fn reduce<T: Clone>(mut partials: Vec<T>, combine: fn(&T, &T) -> T) -> Option<T>{
+    while partials.len() > 1 {
+        partials = partials.chunks(2)
+                           .map(|pair| match pair {
+                               [x, y] => combine(x, y),
+                               _ => pair[0].clone(),
+                           }).collect();
+    }
+    partials.pop()
+}
+
+/// Compare the size of the intersection against the agreed `min_intersection`.
+/// Returns a binary wire that is 1 when the result can be revealed.
+pub fn threshold<F: Fancy>(f: &mut F, count: &CrtBundle<F::Item>, min_intersection: u128)
+    -> Result<F::Item, F::Error>{
+    let q = count.composite_modulus();
+    let min_bundle = f.crt_constant_bundle(min_intersection, q)?;
+    f.crt_geq(count, &min_bundle, "100%")
+}
+
+/// Zero out `x` unless the `reveal` wire is set, so that nothing but
+/// the suppression itself is learned from a result under the threshold.
+pub fn gate<F: Fancy>(f: &mut F, reveal: &F::Item, x: &CrtBundle<F::Item>)
+    -> Result<CrtBundle<F::Item>, F::Error>{
+    let masked = f.mask(reveal, x)?;
+    Ok(CrtBundle::new(masked.wires().to_vec()))
+}
+
+/// Add the noise shares contributed by both parties to `x` before it is revealed.
+pub fn add_noise<F: Fancy>(f: &mut F, x: &CrtBundle<F::Item>, shares: &[CrtBundle<F::Item>])
+    -> Result<CrtBundle<F::Item>, F::Error>{
+    let mut noisy = x.clone();
+    for share in shares{
+        noisy = f.crt_add(&noisy, share)?;
+    }
+    Ok(noisy)
+}
+
+/// Garbler for the join circuit: it reuses the deltas the threads garbled
+/// their partial results with, so that their wires can be combined.
+pub fn join_garbler<C: AbstractChannel>(channel: &mut C, path_delta: &str)
+    -> Result<Garbler<C, AesRng, OtSender>, TwopacError>{
+    let mut gb = Garbler::new(channel.clone(), AesRng::new())?;
+    gb.load_deltas(path_delta);
+    Ok(gb)
+}
+
+/// Evaluator for the join circuit.
+pub fn join_evaluator<C: AbstractChannel>(channel: &mut C)
+    -> Result<Evaluator<C, AesRng, OtReceiver>, TwopacError>{
+    Evaluator::new(channel.clone(), AesRng::new())
+}
+
+/// Garbler side of the noise: the garbler's share is input first, then the evaluator's.
+pub fn garbler_noise<C: AbstractChannel>(gb: &mut Garbler<C, AesRng, OtSender>, x: &CrtBundle<Wire>,
+                                        mechanism: &Mechanism, sensitivity: f64, epsilon: f64, delta: f64)
+    -> Result<CrtBundle<Wire>, TwopacError>{
+    let q = x.composite_modulus();
+    let share = noise::noise_share(mechanism, sensitivity, epsilon, delta, &mut AesRng::new());
+    let garbler_share = gb.crt_encode(noise::to_modular(share, q), q)?;
+    let evaluator_share = gb.crt_receive(q)?;
+    add_noise(gb, x, &[garbler_share, evaluator_share])
+}
+
+/// Evaluator side of the noise: the garbler's share is received first, then the evaluator's is input.
+pub fn evaluator_noise<C: AbstractChannel>(ev: &mut Evaluator<C, AesRng, OtReceiver>, x: &CrtBundle<Wire>,
+                                        mechanism: &Mechanism, sensitivity: f64, epsilon: f64, delta: f64)
+    -> Result<CrtBundle<Wire>, TwopacError>{
+    let q = x.composite_modulus();
+    let share = noise::noise_share(mechanism, sensitivity, epsilon, delta, &mut AesRng::new());
+    let garbler_share = ev.crt_receive(q)?;
+    let evaluator_share = ev.crt_encode(noise::to_modular(share, q), q)?;
+    add_noise(ev, x, &[garbler_share, evaluator_share])
+}
+
+/// Garbler side of a shared output: `x` is masked with a random value before being output
+/// to the evaluator, and the garbler keeps the complement of the mask as its share.
+pub fn garbler_share<C: AbstractChannel>(gb: &mut Garbler<C, AesRng, OtSender>, x: &CrtBundle<Wire>)
+    -> Result<(CrtBundle<Wire>, u128), TwopacError>{
+    let q = x.composite_modulus();
+    let mask = AesRng::new().gen::<u128>() % q;
+    let mask_bundle = gb.crt_encode(mask, q)?;
+    let masked = gb.crt_add(x, &mask_bundle)?;
+    Ok((masked, output::complement_share(mask, q)))
+}
+
+/// Evaluator side of a shared output: the masked `x` it decodes is its share.
+pub fn evaluator_share<C: AbstractChannel>(ev: &mut Evaluator<C, AesRng, OtReceiver>, x: &CrtBundle<Wire>)
+    -> Result<CrtBundle<Wire>, TwopacError>{
+    let q = x.composite_modulus();
+    let mask_bundle = ev.crt_receive(q)?;
+    ev.crt_add(x, &mask_bundle)
+}
@@ -0,0 +1,905 @@
+// Aggregates defined by an expression over the matched client and server columns.
+//
+// The `aggregate` entry of the configuration is written in a small language:
+//
+//     query      := expr [ '/' expr ] [ 'where' comparison { 'and' comparison } ]
+//     expr       := term { ('+' | '-') term }
+//     term       := atom { '*' atom }
+//     atom       := number | 'sum' '(' product ')' | 'count' '(' ')' | '(' expr ')'
+//     product    := operand { '*' operand }
+//     operand    := number | column
+//     comparison := operand ('==' | '!=' | '<' | '<=' | '>' | '>=') operand
+//     column     := ('c' | 's') '.' name
+//
+// such as `sum(c.amount * s.weight) / sum(s.weight)`, `count() where s.flag == 1` or
+// `sum(c.payload * s.payload) / sum(s.payload) where s.payload > c.threshold`.
+// `c.` refers to a column of the client (receiver) and `s.` to a column of the server
+// (sender), as declared in `client_columns` and `server_columns`.
+//
+// Every `sum` (or `count`) is compiled into a pass of the per-thread computation: its
+// client operands are multiplied into the client column and its server operands into
+// the server column, so that the `acc` of the pass is the sum of the product over the
+// matched pairs. The arithmetic between the sums is compiled into the join circuit, and
+// the final division, if any, is done in the clear on the revealed numerator and denominator.
+//
+// The `where` comparisons between the columns of a single party and constants are evaluated
+// locally by the party owning the columns, which zeroes its column on the rows that do not
+// satisfy them. A comparison between a client and a server column, say `c.x > s.y`, is
+// evaluated in the circuit: its indicator is decomposed over the domain of the narrower
+// column as the sum over every value `t` of `[c.x > t] * [s.y == t]`, each term being a
+// product of a client and a server indicator. The sum is therefore split into one pass per
+// value `t`, the client multiplying its column by `[c.x > t]` and the server by `[s.y == t]`,
+// and the passes are added in the join circuit. Neither party learns which rows satisfy
+// the comparison, at the price of one pass per value of the narrower column.
+//
+// The bound of every column is known from the configuration, which lets the compiler check
+// statically that the outputs cannot wrap around the CRT modulus of the computation, and
+// derive the sensitivity of every output used to calibrate the differentially private noise.
+use fancy_garbling::{
+    CrtBundle,
+    CrtGadgets,
+    Fancy,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+};
+
+use crate::aggregate::{Columns, MatchAggregate, PassSums};
+
+/// The party owning a column.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Party {
+    Client,
+    Server,
+}
+
+impl Party {
+    /// Parse a column written `c.<name>` or `s.<name>`.
+    pub fn parse_column(column: &str) -> Option<(Party, String)> {
+        if let Some(name) = column.strip_prefix("c.") {
+            Some((Party::Client, name.to_owned()))
+        }else{
+            column.strip_prefix("s.").map(|name| (Party::Server, name.to_owned()))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(u64),
+    Identifier(String),
+    Symbol(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(x) => write!(f, "{}", x),
+            Token::Identifier(s) | Token::Symbol(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Number(u64),
+    Column(Party, String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Comparator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Comparator::Eq => "==",
+            Comparator::Ne => "!=",
+            Comparator::Lt => "<",
+            Comparator::Le => "<=",
+            Comparator::Gt => ">",
+            Comparator::Ge => ">=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl Comparator {
+    fn holds(&self, x: u64, y: u64) -> bool {
+        match self {
+            Comparator::Eq => x == y,
+            Comparator::Ne => x != y,
+            Comparator::Lt => x < y,
+            Comparator::Le => x <= y,
+            Comparator::Gt => x > y,
+            Comparator::Ge => x >= y,
+        }
+    }
+
+    // The comparator with its operands swapped: x < y is y > x.
+    fn swap(&self) -> Comparator {
+        match self {
+            Comparator::Eq => Comparator::Eq,
+            Comparator::Ne => Comparator::Ne,
+            Comparator::Lt => Comparator::Gt,
+            Comparator::Le => Comparator::Ge,
+            Comparator::Gt => Comparator::Lt,
+            Comparator::Ge => Comparator::Le,
+        }
+    }
+}
+
+/// The right-hand side of a comparison evaluated by a single party.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Number(u64),
+    Column(String),
+}
+
+/// A comparison between a column of `party` and a constant or another of its columns.
+#[derive(Clone, Debug, PartialEq)]
+struct Comparison {
+    party: Party,
+    column: String,
+    comparator: Comparator,
+    value: Value,
+}
+
+impl Comparison {
+    // Whether every row of the columns satisfies the comparison.
+    fn holds(&self, columns: &Columns) -> Vec<bool> {
+        let x = &columns[&self.column];
+        match &self.value {
+            Value::Number(y) => x.iter().map(|x| self.comparator.holds(*x, *y)).collect(),
+            Value::Column(y) => x.iter()
+                                 .zip(columns[y].iter())
+                                 .map(|(x, y)| self.comparator.holds(*x, *y))
+                                 .collect(),
+        }
+    }
+}
+
+/// A comparison `c.client <comparator> s.server` between a client and a server column.
+#[derive(Clone, Debug, PartialEq)]
+struct Cross {
+    client: String,
+    comparator: Comparator,
+    server: String,
+}
+
+/// A `where` condition.
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    Local(Comparison),
+    Cross(Cross),
+}
+
+/// A sum over the matched pairs, computed by one pass.
+#[derive(Clone, Debug, PartialEq)]
+struct Sum {
+    constant: u64,
+    client: Vec<String>,
+    server: Vec<String>,
+}
+
+/// A pass of the per-thread computation: the part of a sum over the rows
+/// that satisfy the comparisons of both parties.
+#[derive(Clone, Debug, PartialEq)]
+struct Pass {
+    sum: usize,
+    filter: Vec<Comparison>,
+}
+
+// Upper bound on the number of passes the comparisons between the parties may expand into.
+const MAX_PASSES: usize = 256;
+
+/// Arithmetic between the sums, compiled into the join circuit.
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(u64),
+    Sum(usize),
+    Add(Box<Node>, Box<Node>),
+    Sub(Box<Node>, Box<Node>),
+    Mul(Box<Node>, Box<Node>),
+}
+
+/// Static bound on the magnitude of a value, along with the largest change
+/// a single matched pair can cause to it.
+#[derive(Clone, Copy, Debug)]
+struct Bound {
+    max: u128,
+    sensitivity: u128,
+}
+
+/// An aggregate compiled from the configured expression.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    text: String,
+    sums: Vec<Sum>,
+    passes: Vec<Pass>,
+    numerator: Node,
+    denominator: Option<Node>,
+    sensitivities: Vec<f64>,
+}
+
+impl Expression {
+    /// Parse and check `text`. The bounds map the name of every column of each party to
+    /// its largest value; `set_size` bounds the size of the intersection and `payload_size`
+    /// is the bit width of the CRT modulus of the computation.
+    pub fn parse(text: &str, client_bounds: &HashMap<String, u64>, server_bounds: &HashMap<String, u64>,
+                set_size: usize, payload_size: usize) -> Result<Expression, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser{ tokens, position: 0, sums: Vec::new() };
+
+        let numerator = parser.expr()?;
+        let denominator = if parser.eat("/") {
+            Some(parser.expr()?)
+        }else{
+            None
+        };
+        let mut conditions = Vec::new();
+        if parser.eat_identifier("where") {
+            conditions.push(parser.comparison()?);
+            while parser.eat_identifier("and") {
+                conditions.push(parser.comparison()?);
+            }
+        }
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {}", token));
+        }
+
+        if parser.sums.is_empty() {
+            return Err("the expression needs at least one sum or count".to_owned());
+        }
+        check_conditions(&conditions, client_bounds, server_bounds)?;
+        let passes = expand(parser.sums.len(), &conditions, client_bounds, server_bounds)?;
+        let mut expression = Expression{
+            text: text.to_owned(),
+            sums: parser.sums,
+            passes,
+            numerator,
+            denominator,
+            sensitivities: Vec::new(),
+        };
+        expression.sensitivities = expression.check(client_bounds, server_bounds, set_size, payload_size)?;
+        Ok(expression)
+    }
+
+    // Check that the columns input by each party fit in the payloads and that no output
+    // can exceed half the modulus, as the outputs are read as signed values.
+    // Returns the sensitivity of every output. A matched pair contributes to a single
+    // pass of every sum, so that the passes do not change the bounds of the sum.
+    fn check(&self, client_bounds: &HashMap<String, u64>, server_bounds: &HashMap<String, u64>,
+            set_size: usize, payload_size: usize) -> Result<Vec<f64>, String> {
+        let limit = 1u128.checked_shl(payload_size as u32 - 1).unwrap_or(u128::MAX);
+
+        let mut sums = Vec::new();
+        for sum in self.sums.iter() {
+            let client = column_product(sum.constant as u128, &sum.client, client_bounds)?;
+            let server = column_product(1, &sum.server, server_bounds)?;
+            if client > u64::MAX as u128 || client >= limit || server >= limit {
+                return Err(format!("the columns of a sum do not fit in a payload of {} bits", payload_size));
+            }
+            let row = client.checked_mul(server).ok_or("a sum overflows")?;
+            let max = row.checked_mul(set_size as u128).ok_or("a sum overflows")?;
+            sums.push(Bound{ max, sensitivity: row });
+        }
+
+        let mut outputs = vec![bound(&self.numerator, &sums)?];
+        if let Some(denominator) = &self.denominator {
+            outputs.push(bound(denominator, &sums)?);
+        }
+        for output in outputs.iter() {
+            if output.max >= limit {
+                return Err(format!("the output needs {} bits, more than the {} bits available",
+                                   128 - output.max.leading_zeros() + 1, payload_size));
+            }
+        }
+        Ok(outputs.iter().map(|output| output.sensitivity as f64).collect())
+    }
+
+    // Zero the rows of `column` that do not satisfy the comparisons of `party` in `pass`.
+    fn apply_filter(&self, pass: usize, party: Party, columns: &Columns, mut column: Vec<u64>) -> Vec<u64> {
+        for comparison in self.passes[pass].filter.iter().filter(|comparison| comparison.party == party) {
+            for (x, holds) in column.iter_mut().zip(comparison.holds(columns)) {
+                if !holds {
+                    *x = 0;
+                }
+            }
+        }
+        column
+    }
+
+    fn circuit<F: Fancy>(&self, f: &mut F, node: &Node, sums: &[PassSums<F::Item>])
+        -> Result<CrtBundle<F::Item>, F::Error>{
+        match node {
+            Node::Number(x) => {
+                let q = sums[0].0.composite_modulus();
+                f.crt_constant_bundle(*x as u128 % q, q)
+            }
+            // The sum is the total of its passes, zero when none of its passes can match
+            Node::Sum(i) => {
+                let mut accs = self.passes.iter()
+                                          .zip(sums.iter())
+                                          .filter(|(pass, _)| pass.sum == *i)
+                                          .map(|(_, (acc, _))| acc);
+                match accs.next() {
+                    Some(acc) => {
+                        let mut total = acc.clone();
+                        for acc in accs {
+                            total = f.crt_add(&total, acc)?;
+                        }
+                        Ok(total)
+                    }
+                    None => {
+                        let q = sums[0].0.composite_modulus();
+                        f.crt_constant_bundle(0, q)
+                    }
+                }
+            }
+            Node::Add(x, y) => {
+                let x = self.circuit(f, x, sums)?;
+                let y = self.circuit(f, y, sums)?;
+                f.crt_add(&x, &y)
+            }
+            Node::Sub(x, y) => {
+                let x = self.circuit(f, x, sums)?;
+                let y = self.circuit(f, y, sums)?;
+                f.crt_sub(&x, &y)
+            }
+            // Multiplications by a constant do not need any gate
+            Node::Mul(x, y) => match (&**x, &**y) {
+                (Node::Number(c), z) | (z, Node::Number(c)) => {
+                    let z = self.circuit(f, z, sums)?;
+                    f.crt_cmul(&z, *c as u128)
+                }
+                _ => {
+                    let x = self.circuit(f, x, sums)?;
+                    let y = self.circuit(f, y, sums)?;
+                    f.crt_mul(&x, &y)
+                }
+            },
+        }
+    }
+}
+
+impl MatchAggregate for Expression {
+    type Output = i128;
+
+    fn name(&self) -> String {
+        self.text.clone()
+    }
+
+    fn npasses(&self) -> usize {
+        self.passes.len()
+    }
+
+    fn client_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        let sum = &self.sums[self.passes[pass].sum];
+        let column = product(sum.constant, &sum.client, columns);
+        self.apply_filter(pass, Party::Client, columns, column)
+    }
+
+    fn server_column(&self, pass: usize, columns: &Columns) -> Vec<u64> {
+        let sum = &self.sums[self.passes[pass].sum];
+        let column = product(1, &sum.server, columns);
+        self.apply_filter(pass, Party::Server, columns, column)
+    }
+
+    // A pass of a sum without server columns, that the server does not filter
+    fn count_pass(&self) -> Option<usize> {
+        self.passes.iter()
+                   .position(|pass| self.sums[pass.sum].server.is_empty() &&
+                                    pass.filter.iter().all(|comparison| comparison.party != Party::Server))
+    }
+
+    fn output<F: Fancy>(&self, f: &mut F, sums: &[PassSums<F::Item>])
+        -> Result<Vec<CrtBundle<F::Item>>, F::Error>{
+        let mut outputs = vec![self.circuit(f, &self.numerator, sums)?];
+        if let Some(denominator) = &self.denominator {
+            outputs.push(self.circuit(f, denominator, sums)?);
+        }
+        Ok(outputs)
+    }
+
+    fn sensitivities(&self) -> Vec<f64> {
+        self.sensitivities.clone()
+    }
+
+    // Like the weighted mean, the division is an integer division that is zero
+    // when the denominator is.
+    fn finalize(&self, values: &[i128]) -> i128 {
+        if self.denominator.is_some() {
+            values[0].checked_div(values[1]).unwrap_or(0)
+        }else{
+            values[0]
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    sums: Vec<Sum>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("unexpected end of the expression")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol.to_owned())) {
+            self.position += 1;
+            true
+        }else{
+            false
+        }
+    }
+
+    fn eat_identifier(&mut self, identifier: &str) -> bool {
+        if self.peek() == Some(&Token::Identifier(identifier.to_owned())) {
+            self.position += 1;
+            true
+        }else{
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        }else{
+            match self.peek() {
+                Some(token) => Err(format!("expected {} instead of {}", symbol, token)),
+                None => Err(format!("expected {} at the end of the expression", symbol)),
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Result<Node, String> {
+        let mut node = self.term()?;
+        loop {
+            if self.eat("+") {
+                node = Node::Add(Box::new(node), Box::new(self.term()?));
+            }else if self.eat("-") {
+                node = Node::Sub(Box::new(node), Box::new(self.term()?));
+            }else{
+                return Ok(node);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, String> {
+        let mut node = self.atom()?;
+        while self.eat("*") {
+            node = Node::Mul(Box::new(node), Box::new(self.atom()?));
+        }
+        Ok(node)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.next()? {
+            Token::Number(x) => Ok(Node::Number(x)),
+            Token::Symbol(s) if s == "(" => {
+                let node = self.expr()?;
+                if self.peek() == Some(&Token::Symbol("/".to_owned())) {
+                    return Err("a division can only appear at the top of the expression".to_owned());
+                }
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Identifier(s) if s == "count" => {
+                self.expect("(")?;
+                self.expect(")")?;
+                self.sums.push(Sum{ constant: 1, client: Vec::new(), server: Vec::new() });
+                Ok(Node::Sum(self.sums.len() - 1))
+            }
+            Token::Identifier(s) if s == "sum" => {
+                self.expect("(")?;
+                let mut sum = Sum{ constant: 1, client: Vec::new(), server: Vec::new() };
+                loop {
+                    match self.operand()? {
+                        Operand::Number(x) => {
+                            sum.constant = sum.constant.checked_mul(x).ok_or("constant overflows")?;
+                        }
+                        Operand::Column(Party::Client, name) => sum.client.push(name),
+                        Operand::Column(Party::Server, name) => sum.server.push(name),
+                    }
+                    if !self.eat("*") {
+                        break;
+                    }
+                }
+                self.expect(")")?;
+                self.sums.push(sum);
+                Ok(Node::Sum(self.sums.len() - 1))
+            }
+            token => Err(format!("unexpected {}", token)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next()? {
+            Token::Number(x) => Ok(Operand::Number(x)),
+            Token::Identifier(s) => {
+                let party = match s.as_str() {
+                    "c" => Party::Client,
+                    "s" => Party::Server,
+                    _ => return Err(format!("unknown party {}, expected c or s", s)),
+                };
+                self.expect(".")?;
+                match self.next()? {
+                    Token::Identifier(name) => Ok(Operand::Column(party, name)),
+                    token => Err(format!("expected a column name instead of {}", token)),
+                }
+            }
+            token => Err(format!("expected a column or a number instead of {}", token)),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Condition, String> {
+        let left = self.operand()?;
+        let comparator = match self.next()? {
+            Token::Symbol(s) => match s.as_str() {
+                "==" => Comparator::Eq,
+                "!=" => Comparator::Ne,
+                "<" => Comparator::Lt,
+                "<=" => Comparator::Le,
+                ">" => Comparator::Gt,
+                ">=" => Comparator::Ge,
+                _ => return Err(format!("unknown comparison {}", s)),
+            },
+            token => return Err(format!("expected a comparison instead of {}", token)),
+        };
+        let right = self.operand()?;
+        // The comparisons are normalized with a column on the left,
+        // and the client column on the left of a comparison between the parties.
+        match (left, right) {
+            (Operand::Number(_), Operand::Number(_)) => Err("a comparison needs a column".to_owned()),
+            (Operand::Number(x), Operand::Column(party, column)) =>
+                Ok(Condition::Local(Comparison{ party, column, comparator: comparator.swap(), value: Value::Number(x) })),
+            (Operand::Column(party, column), Operand::Number(x)) =>
+                Ok(Condition::Local(Comparison{ party, column, comparator, value: Value::Number(x) })),
+            (Operand::Column(party, column), Operand::Column(other, value)) if party == other =>
+                Ok(Condition::Local(Comparison{ party, column, comparator, value: Value::Column(value) })),
+            (Operand::Column(Party::Client, client), Operand::Column(_, server)) =>
+                Ok(Condition::Cross(Cross{ client, comparator, server })),
+            (Operand::Column(_, server), Operand::Column(_, client)) =>
+                Ok(Condition::Cross(Cross{ client, comparator: comparator.swap(), server })),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        }else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let number = chars[start..i].iter().collect::<String>();
+            tokens.push(Token::Number(number.parse::<u64>().map_err(|_| format!("number {} is too large", number))?));
+        }else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Identifier(chars[start..i].iter().collect()));
+        }else if i + 1 < chars.len() && ["==", "!=", "<=", ">="].contains(&&*chars[i..i + 2].iter().collect::<String>()) {
+            tokens.push(Token::Symbol(chars[i..i + 2].iter().collect()));
+            i += 2;
+        }else if "().*/+-<>".contains(c) {
+            tokens.push(Token::Symbol(c.to_string()));
+            i += 1;
+        }else{
+            return Err(format!("unexpected character {}", c));
+        }
+    }
+    Ok(tokens)
+}
+
+fn bounds_of<'a>(party: &Party, client_bounds: &'a HashMap<String, u64>, server_bounds: &'a HashMap<String, u64>)
+    -> &'a HashMap<String, u64> {
+    match party {
+        Party::Client => client_bounds,
+        Party::Server => server_bounds,
+    }
+}
+
+// Check that the columns of every condition exist.
+fn check_conditions(conditions: &[Condition], client_bounds: &HashMap<String, u64>,
+                    server_bounds: &HashMap<String, u64>) -> Result<(), String> {
+    let exists = |party: &Party, column: &str| {
+        bounds_of(party, client_bounds, server_bounds)
+            .get(column)
+            .map(|_| ())
+            .ok_or_else(|| format!("unknown column {}", column))
+    };
+    for condition in conditions {
+        match condition {
+            Condition::Local(comparison) => {
+                exists(&comparison.party, &comparison.column)?;
+                if let Value::Column(column) = &comparison.value {
+                    exists(&comparison.party, column)?;
+                }
+            }
+            Condition::Cross(cross) => {
+                exists(&Party::Client, &cross.client)?;
+                exists(&Party::Server, &cross.server)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Split every sum into passes, one per combination of the values the comparisons between
+// the parties are decomposed over. Each value `t` of the narrower column turns `c.x < s.y`
+// into `c.x < t` for the client and `s.y == t` for the server (or `c.x == t` and `s.y > t`),
+// skipping the values for which the other party's comparison can never hold.
+fn expand(nsums: usize, conditions: &[Condition], client_bounds: &HashMap<String, u64>,
+        server_bounds: &HashMap<String, u64>) -> Result<Vec<Pass>, String> {
+    let mut filters = vec![Vec::new()];
+    for condition in conditions {
+        match condition {
+            Condition::Local(comparison) => {
+                for filter in filters.iter_mut() {
+                    filter.push(comparison.clone());
+                }
+            }
+            Condition::Cross(cross) => {
+                let (max_client, max_server) = (client_bounds[&cross.client], server_bounds[&cross.server]);
+                let nvalues = max_client.min(max_server) as u128 + 1;
+                if nvalues * (filters.len() * nsums) as u128 > MAX_PASSES as u128 {
+                    return Err(format!("the comparisons between the parties need more than {} passes", MAX_PASSES));
+                }
+                let terms = if max_server <= max_client {
+                    (0..=max_server)
+                        .filter(|t| satisfiable(&cross.comparator, *t, max_client))
+                        .map(|t| (Comparison{ party: Party::Client, column: cross.client.clone(),
+                                              comparator: cross.comparator.clone(), value: Value::Number(t) },
+                                  Comparison{ party: Party::Server, column: cross.server.clone(),
+                                              comparator: Comparator::Eq, value: Value::Number(t) }))
+                        .collect::<Vec<(Comparison, Comparison)>>()
+                }else{
+                    (0..=max_client)
+                        .filter(|t| satisfiable(&cross.comparator.swap(), *t, max_server))
+                        .map(|t| (Comparison{ party: Party::Client, column: cross.client.clone(),
+                                              comparator: Comparator::Eq, value: Value::Number(t) },
+                                  Comparison{ party: Party::Server, column: cross.server.clone(),
+                                              comparator: cross.comparator.swap(), value: Value::Number(t) }))
+                        .collect::<Vec<(Comparison, Comparison)>>()
+                };
+                if terms.is_empty() {
+                    return Err(format!("c.{} {} s.{} can never hold", cross.client, cross.comparator, cross.server));
+                }
+                filters = filters.iter()
+                                 .flat_map(|filter| terms.iter().map(move |(client, server)| {
+                                    let mut filter = filter.clone();
+                                    filter.push(client.clone());
+                                    filter.push(server.clone());
+                                    filter
+                                 }))
+                                 .collect();
+            }
+        }
+    }
+
+    Ok((0..nsums).flat_map(|sum| filters.iter().map(move |filter| Pass{ sum, filter: filter.clone() }))
+                 .collect())
+}
+
+// Whether `x <comparator> t` holds for some `x` between 0 and `max`.
+fn satisfiable(comparator: &Comparator, t: u64, max: u64) -> bool {
+    match comparator {
+        Comparator::Eq | Comparator::Ge => t <= max,
+        Comparator::Ne => max > 0 || t != 0,
+        Comparator::Lt => t > 0,
+        Comparator::Le => true,
+        Comparator::Gt => t < max,
+    }
+}
+
+// Largest value of the product of `constant` and the given columns.
+fn column_product(constant: u128, names: &[String], bounds: &HashMap<String, u64>) -> Result<u128, String> {
+    let mut max = constant;
+    for name in names {
+        let bound = bounds.get(name).ok_or_else(|| format!("unknown column {}", name))?;
+        max = max.checked_mul(*bound as u128).ok_or("a sum overflows")?;
+    }
+    Ok(max)
+}
+
+fn bound(node: &Node, sums: &[Bound]) -> Result<Bound, String> {
+    let overflow = || "the expression overflows".to_owned();
+    match node {
+        Node::Number(x) => Ok(Bound{ max: *x as u128, sensitivity: 0 }),
+        Node::Sum(i) => Ok(sums[*i]),
+        // The difference of two values is bounded in magnitude by their sum
+        Node::Add(x, y) | Node::Sub(x, y) => {
+            let (x, y) = (bound(x, sums)?, bound(y, sums)?);
+            Ok(Bound{
+                max: x.max.checked_add(y.max).ok_or_else(overflow)?,
+                sensitivity: x.sensitivity.checked_add(y.sensitivity).ok_or_else(overflow)?,
+            })
+        }
+        // A matched pair changes x*y by at most dx*y + dy*x + dx*dy
+        Node::Mul(x, y) => {
+            let (x, y) = (bound(x, sums)?, bound(y, sums)?);
+            let max = x.max.checked_mul(y.max).ok_or_else(overflow)?;
+            let sensitivity = x.sensitivity.checked_mul(y.max)
+                .and_then(|s| s.checked_add(y.sensitivity.checked_mul(x.max)?))
+                .and_then(|s| s.checked_add(x.sensitivity.checked_mul(y.sensitivity)?))
+                .ok_or_else(overflow)?;
+            Ok(Bound{ max, sensitivity })
+        }
+    }
+}
+
+// Product of `constant` and the given columns, row by row.
+fn product(constant: u64, names: &[String], columns: &Columns) -> Vec<u64> {
+    let n = columns.values().next().map(|column| column.len()).unwrap_or(0);
+    let mut column = vec![constant; n];
+    for name in names {
+        for (x, value) in column.iter_mut().zip(columns[name].iter()) {
+            *x = x.checked_mul(*value)
+                  .unwrap_or_else(|| panic!("the product of the columns overflows at column {}", name));
+        }
+    }
+    column
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(columns: &[(&str, u64)]) -> HashMap<String, u64> {
+        columns.iter().map(|(name, bound)| (name.to_string(), *bound)).collect()
+    }
+
+    fn column(name: &str, values: Vec<u64>) -> Columns {
+        let mut columns = Columns::new();
+        columns.insert(name.to_owned(), values);
+        columns
+    }
+
+    fn parse(text: &str) -> Result<Expression, String> {
+        let client = bounds(&[("amount", 1000), ("threshold", 3)]);
+        let server = bounds(&[("weight", 100), ("flag", 1)]);
+        Expression::parse(text, &client, &server, 1000, 64)
+    }
+
+    #[test]
+    fn tokenize_symbols() {
+        let tokens = tokenize("sum(c.x*2) >= 10").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Identifier("sum".to_owned()), Token::Symbol("(".to_owned()),
+            Token::Identifier("c".to_owned()), Token::Symbol(".".to_owned()),
+            Token::Identifier("x".to_owned()), Token::Symbol("*".to_owned()),
+            Token::Number(2), Token::Symbol(")".to_owned()),
+            Token::Symbol(">=".to_owned()), Token::Number(10),
+        ]);
+        assert!(tokenize("sum(c.x) % 2").is_err());
+        assert!(tokenize("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn parse_weighted_mean() {
+        let expression = parse("sum(c.amount * s.weight) / sum(s.weight)").unwrap();
+        assert_eq!(expression.sums, vec![
+            Sum{ constant: 1, client: vec!["amount".to_owned()], server: vec!["weight".to_owned()] },
+            Sum{ constant: 1, client: Vec::new(), server: vec!["weight".to_owned()] },
+        ]);
+        assert_eq!(expression.numerator, Node::Sum(0));
+        assert_eq!(expression.denominator, Some(Node::Sum(1)));
+        assert_eq!(expression.npasses(), 2);
+        assert_eq!(expression.sensitivities(), vec![100_000.0, 100.0]);
+        assert_eq!(expression.finalize(&[7, 2]), 3);
+        assert_eq!(expression.finalize(&[7, 0]), 0);
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let expression = parse("2 * count() + sum(3 * s.flag) - count()").unwrap();
+        assert_eq!(expression.sums[1].constant, 3);
+        assert_eq!(expression.numerator, Node::Sub(
+            Box::new(Node::Add(
+                Box::new(Node::Mul(Box::new(Node::Number(2)), Box::new(Node::Sum(0)))),
+                Box::new(Node::Sum(1)),
+            )),
+            Box::new(Node::Sum(2)),
+        ));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("1 + 2").is_err());
+        assert!(parse("sum(c.amount").is_err());
+        assert!(parse("sum(x.amount)").is_err());
+        assert!(parse("sum(c.unknown)").is_err());
+        assert!(parse("(count() / count())").is_err());
+        assert!(parse("count() count()").is_err());
+        assert!(parse("count() where 1 == 2").is_err());
+    }
+
+    #[test]
+    fn local_conditions() {
+        let expression = parse("count() where s.flag == 1 and 2 > c.threshold").unwrap();
+        assert_eq!(expression.npasses(), 1);
+        let columns = column("threshold", vec![0, 2, 3]);
+        assert_eq!(expression.client_column(0, &columns), vec![1, 0, 0]);
+        let columns = column("flag", vec![1, 0, 1]);
+        assert_eq!(expression.server_column(0, &columns), vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn cross_conditions() {
+        // c.threshold > s.flag is decomposed over the values 0 and 1 of s.flag
+        let expression = parse("count() where c.threshold > s.flag").unwrap();
+        assert_eq!(expression.npasses(), 2);
+        let client = column("threshold", vec![0, 1, 2]);
+        let server = column("flag", vec![0, 1, 1]);
+        let matched = (0..2).map(|pass| {
+            expression.client_column(pass, &client)
+                      .iter()
+                      .zip(expression.server_column(pass, &server))
+                      .map(|(c, s)| c * s)
+                      .collect::<Vec<u64>>()
+        }).fold(vec![0; 3], |total, pass| total.iter().zip(pass).map(|(x, y)| x + y).collect());
+        assert_eq!(matched, vec![0, 0, 1]);
+
+        assert!(parse("count() where c.threshold > s.weight").is_ok());
+        assert!(parse("count() where c.amount > s.weight and c.threshold < s.weight").is_err());
+        assert!(parse("count() where s.flag > c.threshold and c.threshold < s.flag").is_ok());
+    }
+
+    #[test]
+    fn count_pass() {
+        assert_eq!(parse("sum(c.amount * s.weight) / sum(c.amount)").unwrap().count_pass(), Some(1));
+        assert_eq!(parse("count() where 2 > c.threshold").unwrap().count_pass(), Some(0));
+        assert_eq!(parse("sum(c.amount) where s.flag == 1").unwrap().count_pass(), None);
+        assert_eq!(parse("count() where c.threshold > s.flag").unwrap().count_pass(), None);
+        assert_eq!(parse("sum(s.weight)").unwrap().count_pass(), None);
+    }
+
+    #[test]
+    fn bounds_check() {
+        let client = bounds(&[("x", u32::MAX as u64)]);
+        let server = bounds(&[("y", u32::MAX as u64)]);
+        assert!(Expression::parse("sum(c.x * s.y)", &client, &server, 1, 66).is_ok());
+        assert!(Expression::parse("sum(c.x * s.y)", &client, &server, 1, 64).is_err());
+        assert!(Expression::parse("sum(c.x * s.y)", &client, &server, 1000, 66).is_err());
+        assert!(Expression::parse("sum(c.x * c.x * c.x)", &client, &server, 1, 128).is_err());
+        assert!(Expression::parse("sum(c.x) * sum(s.y)", &client, &server, 1, 128).is_ok());
+
+        let expression = Expression::parse("sum(c.x) * sum(s.y)", &client, &server, 10, 128).unwrap();
+        let x = u32::MAX as f64;
+        assert_eq!(expression.sensitivities(), vec![x * 10.0 * x * 2.0 + x * x]);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflows")]
+    fn product_overflow() {
+        let columns = column("x", vec![u64::MAX]);
+        product(2, &["x".to_owned()], &columns);
+    }
+}
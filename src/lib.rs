@@ -1 +1,6 @@
+pub mod aggregate;
+pub mod circuit;
+pub mod expression;
+pub mod noise;
+pub mod output;
 pub mod util;
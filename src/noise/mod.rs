@@ -0,0 +1,87 @@
+// Differentially private noise added to the aggregates before they are revealed.
+// Each party samples a share of the noise in the clear and inputs it into the
+// join circuit: the noise that ends up in the output is the sum of both shares,
+// so that neither party knows it. Every share is calibrated to the full privacy
+// budget on its own, so that the output is still private against a party that
+// removes the share it knows. The output then carries twice the variance.
+use rand::{CryptoRng, Rng};
+
+/// The distribution of the noise added to the revealed aggregates.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mechanism {
+    /// Discrete Laplace noise, giving pure epsilon-differential privacy.
+    Laplace,
+    /// Discrete Gaussian noise, giving (epsilon, delta)-differential privacy.
+    Gaussian,
+}
+
+impl Mechanism {
+    /// Parse the `dp_mechanism` of the configuration. `none` disables the noise.
+    pub fn parse(mechanism: &str) -> Option<Mechanism> {
+        match mechanism {
+            "none" => None,
+            "laplace" => Some(Mechanism::Laplace),
+            "gaussian" => Some(Mechanism::Gaussian),
+            _ => panic!("unknown dp_mechanism {}, expected none, laplace or gaussian", mechanism),
+        }
+    }
+}
+
+/// Sample one party's share of the noise for a statistic of the given sensitivity.
+///
+/// The share of the Laplace mechanism is a discrete Laplace variable, the difference of two
+/// geometric variables. The share of the Gaussian mechanism is a discrete Gaussian, whose
+/// variance is calibrated for epsilon below 1.
+pub fn noise_share<RNG: CryptoRng + Rng>(mechanism: &Mechanism, sensitivity: f64, epsilon: f64,
+                                        delta: f64, rng: &mut RNG) -> i64 {
+    match mechanism {
+        Mechanism::Laplace => {
+            let p = 1.0 - (-epsilon / sensitivity).exp();
+            geometric(p, rng) as i64 - geometric(p, rng) as i64
+        }
+        Mechanism::Gaussian => {
+            let sigma = sensitivity * (2.0 * (1.25 / delta).ln()).sqrt() / epsilon;
+            discrete_gaussian(sigma * sigma, rng)
+        }
+    }
+}
+
+/// Map a signed value to its representative modulo `q`.
+pub fn to_modular(x: i64, q: u128) -> u128 {
+    if x >= 0 {
+        x as u128 % q
+    } else {
+        q - ((-x) as u128 % q)
+    }
+}
+
+/// Map a value modulo `q` back to a signed value, assuming its magnitude is below `q/2`.
+pub fn from_modular(x: u128, q: u128) -> i128 {
+    if x > q / 2 {
+        x as i128 - q as i128
+    } else {
+        x as i128
+    }
+}
+
+// Uniform sample in (0, 1], safe to take the logarithm of.
+fn uniform<RNG: CryptoRng + Rng>(rng: &mut RNG) -> f64 {
+    1.0 - rng.gen::<f64>()
+}
+
+fn geometric<RNG: CryptoRng + Rng>(p: f64, rng: &mut RNG) -> u64 {
+    (uniform(rng).ln() / (1.0 - p).ln()).floor() as u64
+}
+
+// Canonne, Kamath & Steinke's rejection sampler from the discrete Laplace distribution.
+fn discrete_gaussian<RNG: CryptoRng + Rng>(variance: f64, rng: &mut RNG) -> i64 {
+    let t = variance.sqrt().floor() + 1.0;
+    let p = 1.0 - (-1.0 / t).exp();
+    loop {
+        let y = geometric(p, rng) as i64 - geometric(p, rng) as i64;
+        let z = (y.abs() as f64) - variance / t;
+        if rng.gen::<f64>() < (-z * z / (2.0 * variance)).exp() {
+            return y;
+        }
+    }
+}
@@ -0,0 +1,310 @@
+// Outcome of the join circuit and the files it is written to.
+//
+// In `reveal` mode the statistic of every category is delivered to the
+// `output_recipient` (receiver, sender or both), which writes it to `result.txt`.
+// In `shared` mode the values output by the join circuit are left additively
+// shared between the parties: each party writes its share to `share.txt`,
+// and the `reconstruct-shares` binary recovers the statistic from both files.
+//
+// The share file is a text file of the form:
+//
+//     modulus: <q>
+//     category, shares
+//     0, <share of the first output>, <share of the second output>, ...
+//     1, suppressed
+//
+// where the shares of both parties add up to the output values modulo `q`, the
+// composite CRT modulus of the computation. Categories under the minimum intersection
+// size are marked as suppressed.
+//
+// The shares are those of the outputs of the join circuit, the bundles returned by
+// `MatchAggregate::output`, and not of the statistic: the join circuit does not divide.
+// For the weighted mean, every line holds a share of the numerator `sum(c * s)` followed
+// by a share of the denominator `sum(s)`. A later computation on the shares has to divide
+// them itself, while `reconstruct-shares` recovers both values and divides them in the clear.
+use scuttlebutt::AbstractChannel;
+use std::{
+    fmt,
+    fs::{read_to_string, write},
+    io::Error,
+    path::PathBuf,
+};
+
+use crate::noise;
+
+/// How the result of the join circuit is output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputMode {
+    /// The weighted mean is revealed to the receiver.
+    Reveal,
+    /// The outputs of the join circuit, the numerator and the denominator for the weighted
+    /// mean, are additively shared between the parties.
+    Shared,
+}
+
+impl OutputMode {
+    pub fn parse(mode: &str) -> OutputMode {
+        match mode {
+            "reveal" => OutputMode::Reveal,
+            "shared" => OutputMode::Shared,
+            _ => panic!("unknown output_mode {}, expected reveal or shared", mode),
+        }
+    }
+}
+
+/// Which party learns the weighted mean in `reveal` mode.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recipient {
+    Receiver,
+    Sender,
+    Both,
+}
+
+impl Recipient {
+    pub fn parse(recipient: &str) -> Recipient {
+        match recipient {
+            "receiver" => Recipient::Receiver,
+            "sender" => Recipient::Sender,
+            "both" => Recipient::Both,
+            _ => panic!("unknown output_recipient {}, expected receiver, sender or both", recipient),
+        }
+    }
+
+    pub fn receiver_learns(&self) -> bool {
+        *self != Recipient::Sender
+    }
+
+    pub fn sender_learns(&self) -> bool {
+        *self != Recipient::Receiver
+    }
+}
+
+/// What a party learns from the join circuit for one category.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome<T> {
+    /// The intersection was smaller than the minimum intersection size.
+    Suppressed,
+    /// The statistic was revealed.
+    Revealed(T),
+    /// The statistic was only delivered to the other party.
+    Hidden,
+    /// The party's additive shares of the output values modulo `modulus`.
+    Shares {
+        modulus: u128,
+        values: Vec<u128>,
+    },
+}
+
+impl<T> Outcome<T> {
+    /// Apply `f` to the revealed statistic.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Outcome<U> {
+        match self {
+            Outcome::Suppressed => Outcome::Suppressed,
+            Outcome::Revealed(result) => Outcome::Revealed(f(result)),
+            Outcome::Hidden => Outcome::Hidden,
+            Outcome::Shares{modulus, values} => Outcome::Shares{modulus, values},
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Outcome<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Suppressed => write!(f, "suppressed"),
+            Outcome::Revealed(result) => write!(f, "{}", result),
+            Outcome::Hidden => write!(f, "hidden"),
+            Outcome::Shares{values, ..} => {
+                let values = values.iter().map(|value| value.to_string()).collect::<Vec<String>>();
+                write!(f, "{}", values.join(", "))
+            }
+        }
+    }
+}
+
+/// A statistic revealed in fixed point, with `digits` decimal digits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedPoint {
+    pub value: i128,
+    pub digits: u32,
+}
+
+impl FixedPoint {
+    /// Round `x` to `digits` decimal digits.
+    pub fn from_f64(x: f64, digits: u32) -> FixedPoint {
+        FixedPoint{
+            value: (x * 10f64.powi(digits as i32)).round() as i128,
+            digits,
+        }
+    }
+}
+
+impl fmt::Display for FixedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scale = 10u128.pow(self.digits);
+        let sign = if self.value < 0 { "-" } else { "" };
+        let magnitude = self.value.unsigned_abs();
+        if self.digits == 0 {
+            write!(f, "{}{}", sign, magnitude)
+        }else{
+            write!(f, "{}{}.{:0width$}", sign, magnitude / scale, magnitude % scale, width = self.digits as usize)
+        }
+    }
+}
+
+/// Read revealed values modulo `q` as signed values, since the noise may push them below zero.
+pub fn decode(values: &[u128], q: u128) -> Vec<i128> {
+    values.iter().map(|value| noise::from_modular(*value, q)).collect()
+}
+
+/// Forward the outputs decoded by the receiver to the sender.
+pub fn send_outputs<C: AbstractChannel>(channel: &mut C, values: &[u128]) -> Result<(), Error>{
+    for value in values{
+        channel.write_bytes(&value.to_le_bytes())?;
+    }
+    channel.flush()
+}
+
+/// Receive the `n` outputs decoded by the receiver.
+pub fn receive_outputs<C: AbstractChannel>(channel: &mut C, n: usize) -> Result<Vec<u128>, Error>{
+    let mut values = Vec::with_capacity(n);
+    for _ in 0..n{
+        let mut value = [0 as u8; 16];
+        channel.read_bytes(&mut value)?;
+        values.push(u128::from_le_bytes(value));
+    }
+    Ok(values)
+}
+
+/// Write the outcomes of a party next to its data folder `path`:
+/// the shares to `share.txt` in shared mode, the statistics to `result.txt` otherwise.
+pub fn write_outcomes<T: fmt::Display>(path: &mut PathBuf, output_mode: &OutputMode,
+                                    name: &str, outcomes: &[Outcome<T>]){
+    path.pop();
+    if *output_mode == OutputMode::Shared {
+        path.push("share.txt");
+        let path_str = path.clone().into_os_string().into_string().unwrap();
+        write_shares(&path_str, outcomes);
+    }else{
+        path.push("result.txt");
+        let path_str = path.clone().into_os_string().into_string().unwrap();
+        write_results(&path_str, name, outcomes);
+    }
+    path.pop();
+    path.push("data");
+}
+
+/// Write the tables of every category to result.csv, next to result.txt.
+/// With a group-by, the rows are prefixed by their category.
+pub fn write_csv(path: &mut PathBuf, tables: &[Option<Vec<String>>]){
+    path.pop();
+    path.push("result.csv");
+    let mut output_write = String::new();
+    for (category, table) in tables.iter().enumerate(){
+        let lines = match table {
+            Some(lines) => lines,
+            None => continue,
+        };
+        for (i, line) in lines.iter().enumerate(){
+            if tables.len() == 1 {
+                output_write.push_str(&format!("{}\n", line));
+            }else if i > 0 {
+                output_write.push_str(&format!("{}, {}\n", category, line));
+            }else if output_write.is_empty() {
+                output_write.push_str(&format!("category, {}\n", line));
+            }
+        }
+    }
+    write(&path, output_write).expect("Unable to write file");
+    path.pop();
+    path.push("data");
+}
+
+/// Write the statistic `name` of every category to `path`.
+/// Without a group-by, a single result is written.
+pub fn write_results<T: fmt::Display>(path: &str, name: &str, outcomes: &[Outcome<T>]){
+    let output_write = if outcomes.len() == 1 {
+        format!("{}: {}", name, outcomes[0])
+    }else{
+        let mut output_write = format!("Category, {}\n", name);
+        for (category, outcome) in outcomes.iter().enumerate(){
+            output_write.push_str(&format!("{}, {}\n", category, outcome));
+        }
+        output_write
+    };
+
+    write(path, output_write).expect("Unable to write file");
+}
+
+/// Write the shares of every category to `path`, in the format described above.
+/// The modulus is left at 0 when every category was suppressed.
+pub fn write_shares<T: fmt::Display>(path: &str, outcomes: &[Outcome<T>]){
+    let modulus = outcomes.iter()
+                          .find_map(|outcome| match outcome {
+                              Outcome::Shares{modulus, ..} => Some(*modulus),
+                              _ => None,
+                          }).unwrap_or(0);
+    let mut output_write = format!("modulus: {}\n", modulus);
+    output_write.push_str("category, shares\n");
+    for (category, outcome) in outcomes.iter().enumerate(){
+        output_write.push_str(&format!("{}, {}\n", category, outcome));
+    }
+
+    write(path, output_write).expect("Unable to write file");
+}
+
+/// Read back a share file written by `write_shares`.
+pub fn read_shares<T>(path: &str) -> Vec<Outcome<T>>{
+    let content = read_to_string(path).unwrap();
+    let mut lines = content.lines();
+
+    let modulus = lines.next().unwrap()
+                        .trim_start_matches("modulus: ")
+                        .parse::<u128>().unwrap();
+    // Skip the header
+    lines.next();
+
+    lines.filter(|line| !line.is_empty())
+         .map(|line|{
+            let line_split = line.split(", ").collect::<Vec<&str>>();
+            if line_split[1] == "suppressed" {
+                Outcome::Suppressed
+            }else{
+                Outcome::Shares{
+                    modulus,
+                    values: line_split[1..].iter().map(|value| value.parse::<u128>().unwrap()).collect(),
+                }
+            }
+         }).collect()
+}
+
+/// Recombine the shares of both parties into the output values modulo the returned modulus.
+/// Returns `None` when the category was suppressed.
+pub fn reconstruct<T>(share_a: &Outcome<T>, share_b: &Outcome<T>) -> Option<(Vec<u128>, u128)>{
+    match (share_a, share_b) {
+        (Outcome::Shares{modulus, values: values_a},
+         Outcome::Shares{modulus: modulus_b, values: values_b}) => {
+            assert_eq!(modulus, modulus_b, "the shares were computed modulo different moduli");
+            let values = values_a.iter()
+                                 .zip(values_b.iter())
+                                 .map(|(a, b)| add_mod(*a, *b, *modulus))
+                                 .collect();
+            Some((values, *modulus))
+        }
+        _ => None,
+    }
+}
+
+/// Additive share of `value` held by the party that did not receive `value + mask`.
+pub fn complement_share(mask: u128, modulus: u128) -> u128 {
+    (modulus - mask % modulus) % modulus
+}
+
+/// (a + b) mod q without overflowing
+pub fn add_mod(a: u128, b: u128, q: u128) -> u128 {
+    let (a, b) = (a % q, b % q);
+    if a >= q - b {
+        a - (q - b)
+    } else {
+        a + b
+    }
+}
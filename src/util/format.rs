@@ -0,0 +1,203 @@
+// Binary encoding of the garbled partial results of a batch.
+//
+// A file holds the CRT bundles of a batch, one per megabin, which all share the same moduli:
+//
+//   magic "MCPO" | version: u16 | nmoduli: u16 | moduli: nmoduli x u16 | count: u64
+//   | wires: count x nmoduli x 16 bytes | checksum: u64
+//
+// Integers are little endian, every wire is stored as its 128-bit block, and the checksum is
+// the FNV-1a hash of all the bytes before it, so that truncated or corrupted files are
+// detected when they are loaded.
+use fancy_garbling::{
+    CrtBundle,
+    HasModulus,
+    Wire,
+};
+use scuttlebutt::Block;
+use std::convert::TryInto;
+
+const MAGIC: &[u8; 4] = b"MCPO";
+const VERSION: u16 = 1;
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Encode the bundles of a batch, which must all have the same moduli.
+pub fn encode_bundles(bundles: &[CrtBundle<Wire>]) -> Vec<u8> {
+    let moduli = bundles.first()
+                        .map(|bundle| bundle.wires().iter().map(|w| w.modulus()).collect())
+                        .unwrap_or_else(Vec::new);
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(moduli.len() as u16).to_le_bytes());
+    for q in moduli.iter() {
+        bytes.extend_from_slice(&q.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(bundles.len() as u64).to_le_bytes());
+    for bundle in bundles {
+        let wires = bundle.wires();
+        assert!(wires.iter().map(|w| w.modulus()).eq(moduli.iter().cloned()),
+                "the bundles of a batch should have the same moduli");
+        for wire in wires {
+            bytes.extend_from_slice(&u128::from(wire.as_block()).to_le_bytes());
+        }
+    }
+    let hash = checksum(&bytes);
+    bytes.extend_from_slice(&hash.to_le_bytes());
+    bytes
+}
+
+// Reads the fields of a file in order, failing on a truncated file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < n {
+            return Err("the file is truncated".to_owned());
+        }
+        let field = &self.bytes[self.position..self.position + n];
+        self.position = self.position + n;
+        Ok(field)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn u128(&mut self) -> Result<u128, String> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into().unwrap()))
+    }
+}
+
+/// Decode the bundles of a batch, along with their moduli.
+pub fn decode_bundles(bytes: &[u8]) -> Result<(Vec<u16>, Vec<CrtBundle<Wire>>), String> {
+    if bytes.len() < 8 {
+        return Err("the file is truncated".to_owned());
+    }
+    let (content, hash) = bytes.split_at(bytes.len() - 8);
+    let mut reader = Reader{
+        bytes: content,
+        position: 0,
+    };
+    if reader.take(4)? != MAGIC {
+        return Err("the file does not hold partial results".to_owned());
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(format!("unsupported version {}, expected {}", version, VERSION));
+    }
+    if u64::from_le_bytes(hash.try_into().unwrap()) != checksum(content) {
+        return Err("the checksum does not match, the file is truncated or corrupted".to_owned());
+    }
+
+    let nmoduli = reader.u16()? as usize;
+    let moduli = (0..nmoduli).map(|_| reader.u16()).collect::<Result<Vec<u16>, String>>()?;
+    let count = reader.u64()? as usize;
+    if (content.len() - reader.position) as u128 != count as u128 * nmoduli as u128 * 16 {
+        return Err(format!("the file does not hold {} bundles of {} wires", count, nmoduli));
+    }
+    let mut bundles = Vec::with_capacity(count);
+    for _ in 0..count {
+        let wires = moduli.iter()
+                          .map(|q| Ok(Wire::from_block(Block::from(reader.u128()?), *q)))
+                          .collect::<Result<Vec<Wire>, String>>()?;
+        bundles.push(CrtBundle::new(wires));
+    }
+    Ok((moduli, bundles))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULI: [u16; 3] = [2, 3, 5];
+
+    fn bundles(count: usize) -> Vec<CrtBundle<Wire>> {
+        (0..count).map(|i| {
+            CrtBundle::new(MODULI.iter()
+                                 .enumerate()
+                                 .map(|(j, q)| Wire::from_block(Block::from((i * 10 + j) as u128), *q))
+                                 .collect())
+        }).collect()
+    }
+
+    fn blocks(bundles: &[CrtBundle<Wire>]) -> Vec<u128> {
+        bundles.iter().flat_map(|bundle| bundle.wires().iter().map(|w| u128::from(w.as_block()))).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (moduli, decoded) = decode_bundles(&encode_bundles(&bundles(4))).unwrap();
+        assert_eq!(moduli, MODULI.to_vec());
+        assert_eq!(blocks(&decoded), blocks(&bundles(4)));
+        assert!(decoded.iter().all(|bundle| bundle.wires().iter().map(|w| w.modulus()).eq(MODULI.iter().cloned())));
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let (moduli, decoded) = decode_bundles(&encode_bundles(&[])).unwrap();
+        assert!(moduli.is_empty() && decoded.is_empty());
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = encode_bundles(&bundles(4));
+        for length in [0, 4, 7, 20, bytes.len() - 16, bytes.len() - 1].iter() {
+            assert!(decode_bundles(&bytes[..*length]).is_err(), "truncated at {} bytes", length);
+        }
+    }
+
+    #[test]
+    fn corrupted_files_are_rejected() {
+        let bytes = encode_bundles(&bundles(4));
+        for position in [10, 20, bytes.len() - 9, bytes.len() - 1].iter() {
+            let mut corrupted = bytes.clone();
+            corrupted[*position] ^= 1;
+            let error = decode_bundles(&corrupted).unwrap_err();
+            assert!(error.contains("checksum"), "corrupted at {}: {}", position, error);
+        }
+    }
+
+    #[test]
+    fn mismatched_files_are_rejected() {
+        let mut bytes = encode_bundles(&bundles(4));
+        bytes[..4].copy_from_slice(b"XXXX");
+        assert!(decode_bundles(&bytes).unwrap_err().contains("partial results"));
+
+        let mut bytes = encode_bundles(&bundles(4));
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(decode_bundles(&bytes).unwrap_err().contains("version"));
+
+        // A count that disagrees with the wires, with a valid checksum
+        let mut bytes = encode_bundles(&bundles(4));
+        bytes.truncate(bytes.len() - 8);
+        let position = 4 + 2 + 2 + 2 * MODULI.len();
+        bytes[position..position + 8].copy_from_slice(&5u64.to_le_bytes());
+        let hash = checksum(&bytes);
+        bytes.extend_from_slice(&hash.to_le_bytes());
+        assert!(decode_bundles(&bytes).unwrap_err().contains("5 bundles"));
+    }
+
+    #[test]
+    #[should_panic(expected = "same moduli")]
+    fn mixed_moduli_are_refused() {
+        let mut mixed = bundles(2);
+        mixed.push(CrtBundle::new(vec![Wire::from_block(Block::from(0), 7)]));
+        encode_bundles(&mixed);
+    }
+}
@@ -0,0 +1,192 @@
+// The run manifest of a pass records which of its batches of megabins were computed, their
+// partial results being written to the folder of the batch. It is kept in the folder of the
+// pass and rewritten every time a batch finishes, so that an interrupted run can be resumed:
+// the parties exchange their manifests, and only recompute the batches that are missing
+// from either of them.
+use scuttlebutt::AbstractChannel;
+use std::{
+    fs::{read_to_string, rename, File},
+    io::{Error, Write},
+    path::PathBuf,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    /// The round of the statistic the pass was computed for.
+    pub round: usize,
+    /// Whether the partial results of every batch were written.
+    pub finished: Vec<bool>,
+}
+
+impl Manifest {
+    /// The manifest of a pass of `nbatches` batches, none of which has been computed.
+    pub fn new(round: usize, nbatches: usize) -> Manifest {
+        Manifest{
+            round,
+            finished: vec![false; nbatches],
+        }
+    }
+
+    pub fn nbatches(&self) -> usize {
+        self.finished.len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.finished.iter().all(|finished| *finished)
+    }
+
+    /// The batches that are left to compute, in order.
+    pub fn remaining(&self) -> Vec<usize> {
+        (0..self.nbatches()).filter(|batch| !self.finished[*batch]).collect()
+    }
+
+    /// The manifest of the batches that both parties computed.
+    pub fn agree(&self, other: &Manifest) -> Manifest {
+        assert!(self.round == other.round && self.nbatches() == other.nbatches(),
+                "the manifests of the parties do not describe the same pass: round {} with {} batches \
+                 against round {} with {} batches", self.round, self.nbatches(), other.round, other.nbatches());
+        Manifest{
+            round: self.round,
+            finished: self.finished.iter().zip(other.finished.iter()).map(|(x, y)| *x && *y).collect(),
+        }
+    }
+
+    /// Read the manifest from the folder of its pass, `None` if there is none.
+    pub fn read(path: &mut PathBuf) -> Option<Manifest> {
+        path.push("manifest.txt");
+        let path_str = path.clone().into_os_string().into_string().unwrap();
+        path.pop();
+
+        let manifest = read_to_string(&path_str).ok()?;
+        let mut lines = manifest.lines();
+        let mut field = |name: &str| {
+            let line = lines.next().unwrap_or_else(|| panic!("{} is missing its {}", path_str, name));
+            line.strip_prefix(name)
+                .unwrap_or_else(|| panic!("{} is missing its {}", path_str, name))
+                .trim()
+                .to_owned()
+        };
+        let round = field("round").parse::<usize>().unwrap();
+        let nbatches = field("nbatches").parse::<usize>().unwrap();
+        let mut manifest = Manifest::new(round, nbatches);
+        for batch in field("finished").split_whitespace() {
+            manifest.finished[batch.parse::<usize>().unwrap()] = true;
+        }
+        Some(manifest)
+    }
+
+    /// Write the manifest to the folder of its pass. It replaces the previous manifest
+    /// at once, so that it is never left half written.
+    pub fn write(&self, path: &mut PathBuf) {
+        path.push("manifest.tmp");
+        let path_tmp = path.clone().into_os_string().into_string().unwrap();
+        path.pop();
+        path.push("manifest.txt");
+        let path_str = path.clone().into_os_string().into_string().unwrap();
+        path.pop();
+
+        let finished = (0..self.nbatches())
+                        .filter(|batch| self.finished[*batch])
+                        .map(|batch| batch.to_string())
+                        .collect::<Vec<String>>();
+        let manifest = format!("round {}\nnbatches {}\nfinished {}\n", self.round, self.nbatches(),
+                                finished.join(" "));
+        let mut file_manifest = File::create(&path_tmp).unwrap();
+        file_manifest.write_all(manifest.as_bytes()).unwrap();
+        file_manifest.sync_all().unwrap();
+        rename(path_tmp, path_str).unwrap();
+    }
+
+    pub fn send<C: AbstractChannel>(&self, channel: &mut C) -> Result<(), Error> {
+        channel.write_usize(self.round)?;
+        channel.write_usize(self.nbatches())?;
+        for finished in self.finished.iter() {
+            channel.write_bool(*finished)?;
+        }
+        channel.flush()
+    }
+
+    pub fn receive<C: AbstractChannel>(channel: &mut C) -> Result<Manifest, Error> {
+        let round = channel.read_usize()?;
+        let nbatches = channel.read_usize()?;
+        let finished = (0..nbatches).map(|_| channel.read_bool()).collect::<Result<Vec<bool>, Error>>()?;
+        Ok(Manifest{
+            round,
+            finished,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs::{create_dir_all, remove_dir_all, write}};
+
+    // A fresh folder for the pass of a test.
+    fn folder(name: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(format!("match-compute-manifest-{}-{}", name, std::process::id()));
+        let _ = remove_dir_all(&path);
+        create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut path = folder("round-trip");
+        let mut manifest = Manifest::new(2, 5);
+        manifest.finished[1] = true;
+        manifest.finished[4] = true;
+        manifest.write(&mut path);
+        assert_eq!(Manifest::read(&mut path), Some(manifest.clone()));
+        assert_eq!(manifest.remaining(), vec![0, 2, 3]);
+        assert!(!manifest.is_complete());
+
+        // Rewriting replaces the previous manifest
+        manifest.finished = vec![true; 5];
+        manifest.write(&mut path);
+        let read = Manifest::read(&mut path).unwrap();
+        assert!(read.is_complete() && read.remaining().is_empty());
+        remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_manifest() {
+        let mut path = folder("missing");
+        assert_eq!(Manifest::read(&mut path), None);
+        remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "missing its nbatches")]
+    fn malformed_manifest_is_rejected() {
+        let mut path = folder("malformed");
+        path.push("manifest.txt");
+        write(&path, "round 0\nfinished 1\n").unwrap();
+        path.pop();
+        Manifest::read(&mut path);
+    }
+
+    #[test]
+    fn agree_on_the_batches_both_parties_finished() {
+        let mut client = Manifest::new(0, 4);
+        client.finished = vec![true, true, false, true];
+        let mut server = Manifest::new(0, 4);
+        server.finished = vec![true, false, false, true];
+        let agreed = client.agree(&server);
+        assert_eq!(agreed.remaining(), vec![1, 2]);
+        assert_eq!(agreed, server.agree(&client));
+    }
+
+    #[test]
+    #[should_panic(expected = "do not describe the same pass")]
+    fn agree_rejects_other_rounds() {
+        Manifest::new(0, 4).agree(&Manifest::new(1, 4));
+    }
+
+    #[test]
+    #[should_panic(expected = "do not describe the same pass")]
+    fn agree_rejects_other_batches() {
+        Manifest::new(0, 4).agree(&Manifest::new(0, 5));
+    }
+}
@@ -1,9 +1,23 @@
+pub mod format;
+pub mod manifest;
+pub mod remote;
+pub mod tuning;
+
 use std::{
+    convert::TryInto,
     env,
     fs::{File, read_to_string},
-    io::{BufRead, BufReader, stdin, stdout, Read, Write},
+    io::{BufRead, BufReader, Error, stdin, stdout, Read, Write},
     collections::HashMap,
+    net::{TcpListener, TcpStream},
     path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime},
 };
 
 use rand::{CryptoRng, Rng};
@@ -11,9 +25,32 @@ use fancy_garbling::{
      CrtBundle,
      Wire,
 };
-use scuttlebutt::{AesRng, Block512};
+use scuttlebutt::{AesRng, Block, Block512};
 use serde_json;
 
+use crate::{
+    aggregate::{
+        column_bounds,
+        correlation::{Correlation, Moment},
+        histogram::Histogram,
+        lookup::Lookup,
+        membership::Membership,
+        overlap::Jaccard,
+        quantile::Quantile,
+        regression::{Regression, Reveal},
+        ColumnSpec,
+        Columns,
+        PassSums,
+        Statistic,
+    },
+    circuit,
+    expression::{Expression, Party},
+    noise::Mechanism,
+    output::{OutputMode, Recipient},
+};
+use manifest::Manifest;
+use tuning::Setting;
+
 pub fn int_vec_block512(values: Vec<u64>) -> Vec<Block512> {
     values.into_iter()
           .map(|item|{
@@ -25,6 +62,11 @@ pub fn int_vec_block512(values: Vec<u64>) -> Vec<Block512> {
             Block512::from(res_block)
          }).collect()
 }
+pub fn block512_to_int(values: &[Block512]) -> Vec<u64> {
+    values.iter()
+          .map(|item| u64::from_le_bytes(item.prefix(8).try_into().unwrap()))
+          .collect()
+}
 pub fn rand_u64_vec<RNG: CryptoRng + Rng>(n: usize, modulus: u64, rng: &mut RNG) -> Vec<u64>{
     (0..n).map(|_| rng.gen::<u64>()%modulus).collect()
 }
@@ -45,6 +87,27 @@ pub fn generate_dummy_data(set_size: usize, id_size: usize, max_payload: u64)
     (ids, payloads)
 }
 
+pub fn generate_dummy_categories(set_size: usize, ncategories: usize) -> Vec<u64> {
+    let mut rng = AesRng::new();
+    rand_u64_vec(set_size, ncategories as u64, &mut rng)
+}
+
+/// Zero out the payloads (weights) of every row that does not belong to `category`.
+/// Since the circuit accumulates `acc += client_payload * weight` and `sum_weights += weight`,
+/// rows with a zero weight do not contribute to either sum: running the computation on the
+/// masked payloads yields the `acc`/`sum_weights` pair of that category alone.
+pub fn mask_payloads(payloads: &[Block512], categories: &[u64], category: u64) -> Vec<Block512>{
+    payloads.iter()
+            .zip(categories.iter())
+            .map(|(payload, c)|{
+                if *c == category {
+                    *payload
+                }else{
+                    Block512::from([0 as u8; 64])
+                }
+            }).collect()
+}
+
 
 pub fn crt_to_wires(v: &[CrtBundle<Wire>])-> Vec<Vec<Wire>>{
     v.into_iter()
@@ -56,6 +119,249 @@ pub fn wires_to_crt(v: &[Vec<Wire>])-> Vec<CrtBundle<Wire>>{
      .map(|c| CrtBundle::new(c.to_vec())).collect()
 }
 
+/// Folder holding the partial results of `pass` of the statistic for `category`.
+pub fn pass_name(category: usize, pass: usize) -> String{
+    format!("category{}_pass{}", category, pass)
+}
+
+/// Folder holding the partial results of the pass counting the matches of `category`.
+pub fn count_pass_name(category: usize) -> String{
+    format!("category{}_count", category)
+}
+
+/// Name of the folder of a batch of megabins, within the folder of its pass.
+pub fn batch_name(batch: usize) -> String {
+    format!("batch{}", batch)
+}
+
+/// Where the bucketized states are kept until the threads compute them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatesMode {
+    /// The states are handed to the threads in memory.
+    Memory,
+    /// The states are written to the folder of their batch and read back by the threads,
+    /// so that they are checkpointed on disk.
+    Files,
+}
+
+impl StatesMode {
+    pub fn parse(mode: &str) -> StatesMode {
+        match mode {
+            "memory" => StatesMode::Memory,
+            "files" => StatesMode::Files,
+            _ => panic!("unknown states_mode {}, expected memory or files", mode),
+        }
+    }
+}
+
+/// The bucketized states of a pass, grouped into batches of megabins, along with the run
+/// manifest of the pass. A batch is taken by the single thread that pulls it from the queue,
+/// or is missing when it was written to its folder instead.
+pub struct StateBatches<S> {
+    batches: Vec<Mutex<Option<Vec<S>>>>,
+    manifest: Mutex<Manifest>,
+    remaining: Vec<usize>,
+    next: AtomicUsize,
+}
+
+impl<S> StateBatches<S> {
+    /// Group the states into batches of `batch_size` megabins, in order.
+    pub fn new(states: Vec<S>, batch_size: usize, round: usize) -> StateBatches<S> {
+        let mut batches = Vec::new();
+        let mut states = states.into_iter().peekable();
+        while states.peek().is_some() {
+            batches.push(Mutex::new(Some(states.by_ref().take(batch_size).collect())));
+        }
+        let manifest = Manifest::new(round, batches.len());
+        StateBatches{
+            remaining: manifest.remaining(),
+            batches,
+            manifest: Mutex::new(manifest),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// The batches of a resumed pass, whose states were written to disk. Only the
+    /// batches missing from the manifest are left to compute.
+    pub fn resume(manifest: Manifest) -> StateBatches<S> {
+        StateBatches{
+            batches: (0..manifest.nbatches()).map(|_| Mutex::new(None)).collect(),
+            remaining: manifest.remaining(),
+            manifest: Mutex::new(manifest),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Number of batches left to compute.
+    pub fn nremaining(&self) -> usize {
+        self.remaining.len()
+    }
+
+    /// Take the states of `batch` out of memory, `None` if they were already taken.
+    pub fn take(&self, batch: usize) -> Option<Vec<S>> {
+        self.batches[batch].lock().unwrap().take()
+    }
+
+    /// Pull the next batch left to compute from the queue, `None` when it is empty.
+    pub fn pop(&self) -> Option<usize> {
+        self.remaining.get(self.next.fetch_add(1, Ordering::SeqCst)).cloned()
+    }
+
+    /// Write the manifest to the folder of the pass.
+    pub fn write_manifest(&self, path: &mut PathBuf) {
+        self.manifest.lock().unwrap().write(path);
+    }
+
+    /// Record in the manifest, in the folder of the pass, that the partial results of `batch` were written.
+    pub fn finish(&self, batch: usize, path: &mut PathBuf) {
+        let mut manifest = self.manifest.lock().unwrap();
+        manifest.finished[batch] = true;
+        manifest.write(path);
+    }
+}
+
+/// Number of threads that compute the batches of a pass: there is no use for more threads
+/// than batches, and both parties derive the same number from the configuration and the batches.
+pub fn nworkers(nthread: usize, nbatches: usize) -> usize {
+    nthread.min(nbatches)
+}
+
+/// Address of the thread `thread_id` of the parties. The port of the bucketization and of
+/// the join, 3000, is left free, so that the next pass can be bucketized while the threads compute.
+pub fn thread_address(address: &str, thread_id: usize) -> String {
+    format!("{}:{}", address, 3001 + thread_id)
+}
+
+/// Listen on `address` and accept the first connection of the other party. The listener is
+/// closed as soon as the connection is accepted, so that the next pass listens afresh.
+pub fn accept(address: &str) -> TcpStream {
+    println!("Server listening on {}", address);
+    let listener = TcpListener::bind(address).unwrap();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                println!("New connection: {}", stream.peer_addr().unwrap());
+                return stream;
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+            }
+        }
+    }
+}
+
+/// Connect to the other party on `address`, retrying until it listens or `timeout` elapses,
+/// rather than sleeping for a fixed time before connecting.
+pub fn connect(address: &str, timeout: Duration) -> Result<TcpStream, Error> {
+    let start = SystemTime::now();
+    loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if start.elapsed().unwrap() >= timeout => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
+/// The manifest of `pass` left by a previous run, if the pass can be resumed from it: it was
+/// computed for the same `round`, and the states of the batches left to compute are on disk.
+pub fn read_checkpoint(path: &mut PathBuf, pass: &str, round: usize, states_mode: &StatesMode) -> Option<Manifest> {
+    path.push(pass);
+    let manifest = Manifest::read(path);
+    path.pop();
+    manifest.filter(|manifest| manifest.round == round &&
+                               (*states_mode == StatesMode::Files || manifest.is_complete()))
+}
+
+/// Write the garbled partial results of a batch to its folder, in the binary format of `format`.
+pub fn write_partial_outputs(path: &mut PathBuf, acc: &[CrtBundle<Wire>], sum_weights: &[CrtBundle<Wire>]){
+    path.push("output_aggregate.bin");
+    let path_str = path.clone().into_os_string().into_string().unwrap();
+    let mut file_aggregate = File::create(path_str).unwrap();
+    path.pop();
+
+    path.push("output_sum_weights.bin");
+    let path_str = path.clone().into_os_string().into_string().unwrap();
+    let mut file_sum_weights = File::create(path_str).unwrap();
+    path.pop();
+
+    file_aggregate.write_all(&format::encode_bundles(acc)).unwrap();
+    file_sum_weights.write_all(&format::encode_bundles(sum_weights)).unwrap();
+}
+
+// Load the bundles of a partial results file, checking that they have the expected moduli.
+fn read_bundles(path: &mut PathBuf, file: &str, moduli: &mut Option<Vec<u16>>) -> Vec<CrtBundle<Wire>>{
+    path.push(file);
+    let path_str = path.clone().into_os_string().into_string().unwrap();
+    path.pop();
+
+    let bytes = std::fs::read(&path_str).unwrap();
+    let (file_moduli, bundles) = format::decode_bundles(&bytes)
+                                    .unwrap_or_else(|e| panic!("cannot load {}: {}", path_str, e));
+    if bundles.is_empty() {
+        return bundles;
+    }
+    match moduli {
+        Some(moduli) => assert!(*moduli == file_moduli,
+                                "cannot load {}: its moduli {:?} do not match the moduli {:?} of the other batches",
+                                path_str, file_moduli, moduli),
+        None => *moduli = Some(file_moduli),
+    }
+    bundles
+}
+
+/// Sum the garbled partial results of every batch of `pass` in a reduction tree. They do not
+/// depend on which thread computed which batch. In the first stage, `nthread` threads pull the
+/// batches and each adds the megabins it reads to its own sum, so that a single batch per
+/// thread is held in memory. The sums of the threads are then combined pairwise. The partial
+/// results are combined with `combine`, the gadget of the statistic or `circuit::add_sums`.
+pub fn reduce_partial_outputs(path: &mut PathBuf, pass: &str, nthread: usize,
+                              combine: fn(&PassSums<Wire>, &PassSums<Wire>) -> PassSums<Wire>) -> PassSums<Wire>{
+    path.push(pass);
+    let manifest = Manifest::read(path).unwrap_or_else(|| panic!("{} was not computed", pass));
+    assert!(manifest.is_complete(), "batches {:?} of {} did not finish, resume the run", manifest.remaining(), pass);
+
+    let nbatches = manifest.nbatches();
+    let next = Arc::new(AtomicUsize::new(0));
+    let mut handle = Vec::new();
+    for _ in 0..nworkers(nthread, nbatches){
+        let mut path_thread = path.clone();
+        let next = Arc::clone(&next);
+        handle.push(thread::spawn(move || {
+            let mut sums = None;
+            let mut moduli = None;
+            loop {
+                let batch = next.fetch_add(1, Ordering::SeqCst);
+                if batch >= nbatches {
+                    break;
+                }
+                path_thread.push(batch_name(batch));
+                let aggregates = read_bundles(&mut path_thread, "output_aggregate.bin", &mut moduli);
+                let weights = read_bundles(&mut path_thread, "output_sum_weights.bin", &mut moduli);
+                assert!(aggregates.len() == weights.len(), "the partial results of {} do not pair up", batch_name(batch));
+                let mut partials = aggregates.into_iter().zip(weights).collect::<Vec<PassSums<Wire>>>();
+                partials.extend(sums);
+                sums = circuit::reduce(partials, combine);
+                path_thread.pop();
+            }
+            sums
+        }));
+    }
+    path.pop();
+
+    let sums = handle.into_iter()
+                     .filter_map(|thread| thread.join().unwrap())
+                     .collect();
+    circuit::reduce(sums, combine).unwrap_or_else(|| panic!("{} has no partial results", pass))
+}
 
 pub fn pad_data<RNG: CryptoRng + Rng>(ids: &[Vec<u8>], payloads: &[Block512],
                         client_padding: usize, rng: &mut RNG) -> (Vec<Vec<u8>>, Vec<Block512>){
@@ -173,6 +479,46 @@ pub fn parse_files(
     (ids, int_vec_block512(payloads))
 }
 
+/// Parse a single integer column of a csv file, skipping the header.
+pub fn parse_column(position: usize, path: &str) -> Vec<u64> {
+    let data = File::open(path).unwrap();
+
+    let buffer = BufReader::new(data).lines();
+
+    let mut column = Vec::new();
+    for line in buffer.skip(1) {
+        let line_split = line
+            .unwrap()
+            .split(',')
+            .map(|item| item.to_string())
+            .collect::<Vec<String>>();
+        column.push(line_split[position].parse::<u64>().unwrap());
+    }
+    column
+}
+
+/// Gather the payloads and the declared columns of a party, generated at random
+/// when `fake_data` is set and read from the csv at `path` otherwise.
+/// The columns are checked against the bounds the aggregate was compiled for.
+pub fn read_columns(specs: &[ColumnSpec], payloads: &[Block512], max_payload: u64,
+                    path: &str, fake_data: bool) -> Columns{
+    let mut rng = AesRng::new();
+    let mut columns = HashMap::new();
+    columns.insert("payload".to_owned(), block512_to_int(payloads));
+    for spec in specs {
+        let column = if fake_data == true {
+            rand_u64_vec(payloads.len(), spec.max().saturating_add(1), &mut rng)
+        }else{
+            parse_column(spec.position, path)
+        };
+        assert!(column.iter().all(|x| *x <= spec.max()),
+                "column {} has values wider than {} bits", spec.name, spec.bits);
+        columns.insert(spec.name.clone(), column);
+    }
+    assert!(columns["payload"].iter().all(|x| *x < max_payload), "payloads should be below max_payload");
+    columns
+}
+
 pub fn parse_config(path_config: &mut PathBuf) -> HashMap<String, String>{
     path_config.push("config/configuration.txt");
     let absolute_path = path_config.clone().into_os_string().into_string().unwrap();
@@ -204,10 +550,10 @@ pub fn get_config_experiments(parameters: &HashMap<String, String>)->
 }
 
 pub fn get_config_sever(parameters: &HashMap<String, String>)->
-                                    (String, String, usize, usize, usize){
+                                    (String, String, Setting, usize, usize){
     let address = parameters.get("address").unwrap().to_owned();
     let server_path = parameters.get("data_path_server").unwrap().to_owned();
-    let nthread = parameters.get("nthread").unwrap().parse::<usize>().unwrap();
+    let nthread = Setting::parse(parameters.get("nthread").unwrap(), "nthread");
     //
     let id_position = parameters.get("id_position_server").unwrap().parse::<usize>().unwrap();
     let payload_position = parameters.get("payload_position_server").unwrap().parse::<usize>().unwrap();
@@ -216,21 +562,281 @@ pub fn get_config_sever(parameters: &HashMap<String, String>)->
 }
 
 pub fn get_config_client(parameters: &HashMap<String, String>)->
-            (String, String, u64, u32, usize, usize, usize, usize, usize){
+            (String, String, u32, Setting, Setting, usize, usize, usize){
     let address = parameters.get("address").unwrap().to_owned();
     let client_path = parameters.get("data_path_client").unwrap().to_owned();
 
-    let sleeptime = parameters.get("sleeptime").unwrap().parse::<u64>().unwrap();
     let precision = parameters.get("precision").unwrap().parse::<u32>().unwrap();
 
-    let nthread = parameters.get("nthread").unwrap().parse::<usize>().unwrap();
-    let megasize = parameters.get("megasize").unwrap().parse::<usize>().unwrap();
+    let nthread = Setting::parse(parameters.get("nthread").unwrap(), "nthread");
+    let megasize = Setting::parse(parameters.get("megasize").unwrap(), "megasize");
 
     let id_position = parameters.get("id_position_client").unwrap().parse::<usize>().unwrap();
     let payload_position = parameters.get("payload_position_client").unwrap().parse::<usize>().unwrap();
     let client_padding = parameters.get("client_padding").unwrap().parse::<usize>().unwrap();
 
-    (address, client_path, sleeptime, precision, nthread, megasize, client_padding, id_position, payload_position)
+    (address, client_path, precision, nthread, megasize, client_padding, id_position, payload_position)
+}
+
+/// Returns how long the client waits for the server to listen on a port before giving up.
+/// It covers the passes the server is still computing, so it is configured in seconds and
+/// should exceed the longest pass.
+pub fn get_config_connect_timeout(parameters: &HashMap<String, String>)-> Duration{
+    Duration::from_secs(parameters.get("connect_timeout").unwrap().parse::<u64>().unwrap())
+}
+
+/// Returns the memory the threads may use when `nthread` or `megasize` is chosen automatically,
+/// in bytes. It is configured in MB.
+pub fn get_config_memory_budget(parameters: &HashMap<String, String>)-> usize{
+    parameters.get("memory_budget").unwrap().parse::<usize>().unwrap() * 1_000_000
+}
+
+/// Returns the number of megabins per batch the threads pull from the queue.
+pub fn get_config_batch_size(parameters: &HashMap<String, String>)-> usize{
+    let batch_size = parameters.get("megabins_per_batch").unwrap().parse::<usize>().unwrap();
+    assert!(batch_size > 0, "megabins_per_batch should be positive");
+    batch_size
+}
+
+/// Returns the control addresses of the worker processes of the client and of the server, as
+/// `host:port` lists. Without workers, `none`, the batches are computed by local threads.
+pub fn get_config_workers(parameters: &HashMap<String, String>)-> (Vec<String>, Vec<String>){
+    let workers = |key: &str| {
+        let addresses = parameters.get(key).unwrap();
+        if addresses == "none" {
+            Vec::new()
+        }else{
+            addresses.split(',').map(|address| address.trim().to_owned()).collect::<Vec<String>>()
+        }
+    };
+    let client_workers = workers("client_workers");
+    let server_workers = workers("server_workers");
+    assert!(client_workers.len() == server_workers.len(),
+            "every client worker pairs up with a server worker, set as many client_workers as server_workers");
+    (client_workers, server_workers)
+}
+
+/// Returns the key a party shares out of band with its coordinator and workers, read from the
+/// file named by `worker_key`, as 32 hexadecimal digits. It is only needed with workers, and
+/// every party keeps its own, generated for instance with `head -c 16 /dev/urandom | xxd -p`.
+pub fn get_config_worker_key(parameters: &HashMap<String, String>)-> Option<Block>{
+    let path = parameters.get("worker_key").unwrap();
+    if path == "none" {
+        return None;
+    }
+    let key = read_to_string(path).unwrap_or_else(|e| panic!("cannot read the worker key {}: {}", path, e));
+    let key = key.trim();
+    assert!(key.len() == 32, "the worker key should be 32 hexadecimal digits");
+    let key = u128::from_str_radix(key, 16).expect("the worker key should be 32 hexadecimal digits");
+    Some(Block::from(key))
+}
+
+/// Returns whether the run resumes from the manifests and partial results of a previous run.
+pub fn get_config_resume(parameters: &HashMap<String, String>)-> bool{
+    parameters.get("resume").unwrap().parse::<bool>().unwrap()
+}
+
+/// Returns where the bucketized states are kept until the threads compute them.
+pub fn get_config_states_mode(parameters: &HashMap<String, String>)-> StatesMode{
+    StatesMode::parse(parameters.get("states_mode").unwrap())
+}
+
+/// Returns the noise mechanism (if any) and the privacy budget epsilon & delta.
+pub fn get_config_noise(parameters: &HashMap<String, String>)-> (Option<Mechanism>, f64, f64){
+    let mechanism = Mechanism::parse(parameters.get("dp_mechanism").unwrap());
+    let epsilon = parameters.get("dp_epsilon").unwrap().parse::<f64>().unwrap();
+    let delta = parameters.get("dp_delta").unwrap().parse::<f64>().unwrap();
+    if let Some(mechanism) = &mechanism {
+        assert!(epsilon > 0.0, "dp_epsilon should be positive");
+        // The variance of the Gaussian mechanism is only calibrated for epsilon below 1
+        if *mechanism == Mechanism::Gaussian {
+            assert!(epsilon < 1.0, "the gaussian dp_mechanism requires dp_epsilon below 1");
+            assert!(delta > 0.0 && delta < 1.0, "the gaussian dp_mechanism requires dp_delta in (0, 1)");
+        }
+    }
+
+    (mechanism, epsilon, delta)
+}
+
+/// Returns the columns declared under `key` (`client_columns` or `server_columns`).
+pub fn get_config_columns(parameters: &HashMap<String, String>, key: &str)-> Vec<ColumnSpec>{
+    ColumnSpec::parse_list(parameters.get(key).unwrap())
+}
+
+pub fn get_config_statistic(parameters: &HashMap<String, String>)-> Statistic{
+    Statistic::parse(parameters.get("statistic").unwrap())
+}
+
+/// Returns the histogram of the configured column over the configured boundaries.
+pub fn get_config_histogram(parameters: &HashMap<String, String>)-> Histogram{
+    let histogram = Histogram::parse(parameters.get("histogram_column").unwrap(),
+                                     parameters.get("histogram_boundaries").unwrap());
+    get_config_bound(parameters, &histogram.party, &histogram.column)
+        .unwrap_or_else(|| panic!("unknown histogram column {}", histogram.column));
+    histogram
+}
+
+/// Returns the quantile of the configured column. The binary search reveals the quantile
+/// to both parties, and the comparisons it outputs cannot be made noisy.
+pub fn get_config_quantile(parameters: &HashMap<String, String>)-> Quantile{
+    let (_, set_size, _, payload_size, _, _, _) = get_config_experiments(parameters);
+    let column = parameters.get("quantile_column").unwrap();
+    let (party, column) = Party::parse_column(column)
+                                .unwrap_or_else(|| panic!("quantile column {} should start with c. or s.", column));
+    let max = get_config_bound(parameters, &party, &column)
+                .unwrap_or_else(|| panic!("unknown quantile column {}", column));
+
+    let (mechanism, _, _) = get_config_noise(parameters);
+    assert!(mechanism.is_none(), "noise cannot be added to the quantile");
+    assert!(get_config_output_mode(parameters) == OutputMode::Reveal &&
+            get_config_output_recipient(parameters) == Recipient::Both,
+            "the quantile is revealed to both parties, set output_mode: reveal and output_recipient: both");
+
+    let quantile = Quantile::new(party, column, parameters.get("quantile").unwrap(), max);
+    quantile.check(set_size, payload_size).unwrap_or_else(|e| panic!("invalid quantile: {}", e));
+    quantile
+}
+
+/// Returns the membership of the client records, which is only revealed to the client.
+pub fn get_config_membership(parameters: &HashMap<String, String>)-> Membership{
+    let (_, set_size, _, payload_size, _, _, _) = get_config_experiments(parameters);
+
+    let (mechanism, _, _) = get_config_noise(parameters);
+    assert!(mechanism.is_none(), "noise cannot be added to the matched rows");
+    assert!(get_config_output_mode(parameters) == OutputMode::Reveal &&
+            get_config_output_recipient(parameters) == Recipient::Receiver,
+            "the matched rows are revealed to the client, set output_mode: reveal and output_recipient: receiver");
+
+    Membership::new(set_size, payload_size)
+}
+
+/// Returns the lookup of `lookup_column` among the server columns, which is only revealed to the client.
+pub fn get_config_lookup(parameters: &HashMap<String, String>)-> Lookup{
+    let (_, set_size, _, payload_size, _, _, _) = get_config_experiments(parameters);
+    let column = parameters.get("lookup_column").unwrap().to_owned();
+    let max = get_config_bound(parameters, &Party::Server, &column).expect("unknown lookup_column");
+
+    let (mechanism, _, _) = get_config_noise(parameters);
+    assert!(mechanism.is_none(), "noise cannot be added to the labels");
+    assert!(get_config_output_mode(parameters) == OutputMode::Reveal &&
+            get_config_output_recipient(parameters) == Recipient::Receiver,
+            "the labels are revealed to the client, set output_mode: reveal and output_recipient: receiver");
+
+    Lookup::new(column, max, set_size, payload_size)
+}
+
+/// Returns the overlap of the sets, whose sizes are revealed to both parties.
+pub fn get_config_jaccard(parameters: &HashMap<String, String>)-> Jaccard{
+    let digits = parameters.get("output_precision").unwrap().parse::<u32>().unwrap();
+    assert!(get_config_output_mode(parameters) == OutputMode::Reveal,
+            "the overlap is computed from the revealed intersection, set output_mode: reveal");
+    Jaccard::new(digits)
+}
+
+/// Returns the largest value of a column of `party`, `None` if the column is not declared.
+pub fn get_config_bound(parameters: &HashMap<String, String>, party: &Party, column: &str)-> Option<u64>{
+    let (_, _, _, _, max_payload, _, _) = get_config_experiments(parameters);
+    let key = match party {
+        Party::Client => "client_columns",
+        Party::Server => "server_columns",
+    };
+    column_bounds(&get_config_columns(parameters, key), max_payload).get(column).cloned()
+}
+
+/// Returns the covariance or correlation of the configured columns,
+/// checked against the bounds of the columns.
+pub fn get_config_correlation(parameters: &HashMap<String, String>, moment: Moment)-> Correlation{
+    let (_, set_size, _, payload_size, max_payload, _, _) = get_config_experiments(parameters);
+    let client_column = parameters.get("correlation_client_column").unwrap().to_owned();
+    let server_column = parameters.get("correlation_server_column").unwrap().to_owned();
+    let digits = parameters.get("output_precision").unwrap().parse::<u32>().unwrap();
+    let client_bounds = column_bounds(&get_config_columns(parameters, "client_columns"), max_payload);
+    let server_bounds = column_bounds(&get_config_columns(parameters, "server_columns"), max_payload);
+
+    let correlation = Correlation{
+        moment,
+        max_client: *client_bounds.get(&client_column).expect("unknown correlation_client_column"),
+        max_server: *server_bounds.get(&server_column).expect("unknown correlation_server_column"),
+        client_column,
+        server_column,
+        set_size,
+        digits,
+    };
+    correlation.check(payload_size).unwrap_or_else(|e| panic!("invalid correlation: {}", e));
+    correlation
+}
+
+/// Returns the regression of the configured outcome on the configured covariates,
+/// checked against the bounds of the columns. `regression_reveal` is `determinants`, which
+/// reveals det(XᵀX) and the determinants of Cramer's rule the coefficients are divided from,
+/// or `statistics`, which reveals XᵀX and Xᵀy.
+pub fn get_config_regression(parameters: &HashMap<String, String>)-> Regression{
+    let (_, set_size, _, payload_size, max_payload, _, _) = get_config_experiments(parameters);
+    let outcome = parameters.get("regression_outcome").unwrap().to_owned();
+    let covariates = parameters.get("regression_covariates").unwrap()
+                               .split(',')
+                               .map(|covariate| covariate.trim().to_owned())
+                               .collect::<Vec<String>>();
+    let reveal = Reveal::parse(parameters.get("regression_reveal").unwrap());
+    let digits = parameters.get("output_precision").unwrap().parse::<u32>().unwrap();
+    let client_bounds = column_bounds(&get_config_columns(parameters, "client_columns"), max_payload);
+    let server_bounds = column_bounds(&get_config_columns(parameters, "server_columns"), max_payload);
+
+    let (mechanism, _, _) = get_config_noise(parameters);
+    assert!(mechanism.is_none() || reveal == Reveal::Statistics,
+            "noise can only be added to the regression with regression_reveal: statistics");
+
+    let regression = Regression{
+        max_outcome: *client_bounds.get(&outcome).expect("unknown regression_outcome"),
+        max_covariates: covariates.iter()
+                                  .map(|covariate| *server_bounds.get(covariate).expect("unknown regression covariate"))
+                                  .collect(),
+        outcome,
+        covariates,
+        reveal,
+        set_size,
+        digits,
+    };
+    regression.check(payload_size).unwrap_or_else(|e| panic!("invalid regression: {}", e));
+    regression
+}
+
+/// Returns the aggregate expression, parsed and checked against the declared columns.
+pub fn get_config_aggregate(parameters: &HashMap<String, String>)-> Expression{
+    let (_, set_size, _, payload_size, max_payload, _, _) = get_config_experiments(parameters);
+    let text = parameters.get("aggregate").unwrap();
+    let client_bounds = column_bounds(&get_config_columns(parameters, "client_columns"), max_payload);
+    let server_bounds = column_bounds(&get_config_columns(parameters, "server_columns"), max_payload);
+
+    Expression::parse(text, &client_bounds, &server_bounds, set_size, payload_size)
+                .unwrap_or_else(|e| panic!("invalid aggregate {}: {}", text, e))
+}
+
+pub fn get_config_output_mode(parameters: &HashMap<String, String>)-> OutputMode{
+    OutputMode::parse(parameters.get("output_mode").unwrap())
+}
+
+pub fn get_config_output_recipient(parameters: &HashMap<String, String>)-> Recipient{
+    Recipient::parse(parameters.get("output_recipient").unwrap())
+}
+
+/// Returns the minimum number of matched records of a category for its result to be
+/// revealed, 0 revealing every result. The count comes from a pass of the statistic whose
+/// server weights are ones when it has one, and from an additional pass per category otherwise.
+pub fn get_config_min_intersection(parameters: &HashMap<String, String>)-> u128{
+    parameters.get("min_intersection").unwrap().parse::<u128>().unwrap()
+}
+
+/// Returns the number of categories the statistic is grouped by, and the position of the
+/// category column of the server. The server masks its weights to one category at a time,
+/// so every category runs the whole computation again: the bucketization, the PSI of every
+/// pass and the join. A run with `ncategories` categories costs `ncategories` times a run
+/// without grouping, in time and in communication alike.
+pub fn get_config_group_by(parameters: &HashMap<String, String>)-> (usize, usize){
+    let ncategories = parameters.get("ncategories").unwrap().parse::<usize>().unwrap();
+    let category_position = parameters.get("category_position_server").unwrap().parse::<usize>().unwrap();
+
+    (ncategories, category_position)
 }
 
 // Taken from:
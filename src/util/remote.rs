@@ -0,0 +1,375 @@
+// Messages exchanged between the coordinator of a party and its worker processes, when the
+// batches of megabins are computed on other machines than the coordinator's.
+//
+// For every pass, the coordinator sends a `Job` to each of its workers. The server workers
+// listen for the client workers on their pairing port, the port following their control port,
+// and the i-th client worker pairs up with the i-th server worker. The batches are then handed
+// out as with local threads: the server coordinator pulls a batch from its queue and ships its
+// states to a server worker, which tells the client worker it is paired with which batch it
+// took, and the client worker requests the states of that batch from the client coordinator.
+// The workers send the garbled partial results back to their coordinator, in the binary
+// format of `format`, and the coordinators write them to the folder of the batch.
+//
+// The jobs carry the deltas of the server and the states of both parties, which must not
+// leak to the other party or to the network. A coordinator and its workers therefore speak
+// over a `SealedChannel`, keyed with the `worker_key` its party shared with them out of band:
+// every flush of the channel sends records sealed with AES-GCM, under keys derived by HKDF
+// from the worker key and from a fresh nonce of either side, so that the records of a
+// connection cannot be replayed on another. The workers then send their communication with
+// the other party back to their coordinator.
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes128Gcm,
+    Nonce,
+};
+use hkdf::Hkdf;
+use rand::Rng;
+use scuttlebutt::{AbstractChannel, AesRng, Block};
+use sha2::Sha256;
+use std::{
+    cell::RefCell,
+    io::{Error, ErrorKind},
+    rc::Rc,
+};
+
+/// Send a byte string, prefixed by its length.
+pub fn send_bytes<C: AbstractChannel>(channel: &mut C, bytes: &[u8]) -> Result<(), Error> {
+    channel.write_usize(bytes.len())?;
+    channel.write_bytes(bytes)?;
+    channel.flush()
+}
+
+/// Receive a byte string sent by `send_bytes`.
+pub fn receive_bytes<C: AbstractChannel>(channel: &mut C) -> Result<Vec<u8>, Error> {
+    let nbytes = channel.read_usize()?;
+    channel.read_vec(nbytes)
+}
+
+/// The address the server worker with the control address `address` pairs up with the client on.
+pub fn pairing_address(address: &str) -> String {
+    let colon = address.rfind(':').unwrap_or_else(|| panic!("worker address {} has no port", address));
+    let port = address[colon + 1..].parse::<u16>().unwrap();
+    format!("{}:{}", &address[..colon], port + 1)
+}
+
+/// The computation of a pass assigned to a worker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    /// Name of the pass.
+    pub pass: String,
+    pub payload_size: usize,
+    /// For a client worker, the pairing address of the server worker it pairs up with.
+    pub peer: String,
+    /// For a server worker, the deltas the partial results are garbled with.
+    pub deltas: Vec<u8>,
+}
+
+impl Job {
+    pub fn send<C: AbstractChannel>(&self, channel: &mut C) -> Result<(), Error> {
+        send_bytes(channel, self.pass.as_bytes())?;
+        channel.write_usize(self.payload_size)?;
+        send_bytes(channel, self.peer.as_bytes())?;
+        send_bytes(channel, &self.deltas)
+    }
+
+    pub fn receive<C: AbstractChannel>(channel: &mut C) -> Result<Job, Error> {
+        let pass = String::from_utf8(receive_bytes(channel)?).unwrap();
+        let payload_size = channel.read_usize()?;
+        let peer = String::from_utf8(receive_bytes(channel)?).unwrap();
+        let deltas = receive_bytes(channel)?;
+        Ok(Job{
+            pass,
+            payload_size,
+            peer,
+            deltas,
+        })
+    }
+}
+
+// Labels of the keys derived for each direction of a sealed channel.
+const TO_WORKER: &[u8] = b"match-compute coordinator to worker";
+const TO_COORDINATOR: &[u8] = b"match-compute worker to coordinator";
+/// Largest record sent by a sealed channel: longer writes are split into several records,
+/// and longer records are rejected before anything is allocated for them.
+const MAX_RECORD: usize = 1 << 20;
+const TAG_BYTES: usize = 16;
+
+/// The key of one direction of a sealed channel, and the number of records sent in it,
+/// which is the nonce of the next record.
+struct Keys {
+    cipher: Aes128Gcm,
+    record: u64,
+}
+
+impl Keys {
+    fn derive(hkdf: &Hkdf<Sha256>, label: &[u8]) -> Keys {
+        let mut key = [0u8; 16];
+        hkdf.expand(label, &mut key).expect("16 bytes is a valid length for HKDF");
+        Keys{
+            cipher: Aes128Gcm::new_from_slice(&key).expect("AES-128-GCM takes a key of 16 bytes"),
+            record: 0,
+        }
+    }
+
+    fn nonce(&self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&self.record.to_le_bytes());
+        nonce
+    }
+}
+
+struct Sealed<C> {
+    channel: C,
+    send: Keys,
+    receive: Keys,
+    // Bytes written since the last flush
+    written: Vec<u8>,
+    // Bytes of the last record received that were not read yet
+    received: Vec<u8>,
+    position: usize,
+}
+
+impl<C: AbstractChannel> Sealed<C> {
+    // The length of the record is authenticated along with its content.
+    fn seal(&mut self, record: &[u8]) -> Result<(), Error> {
+        let length = (record.len() as u64).to_le_bytes();
+        let sealed = self.send.cipher.encrypt(Nonce::from_slice(&self.send.nonce()), Payload{ msg: record, aad: &length })
+                                     .map_err(|_| Error::new(ErrorKind::InvalidInput, "a record of the worker channel cannot be sealed"))?;
+        self.send.record += 1;
+        self.channel.write_bytes(&length)?;
+        self.channel.write_bytes(&sealed)
+    }
+
+    fn open(&mut self) -> Result<(), Error> {
+        let mut length = [0u8; 8];
+        self.channel.read_bytes(&mut length)?;
+        let nbytes = u64::from_le_bytes(length);
+        if nbytes > MAX_RECORD as u64 {
+            return Err(Error::new(ErrorKind::InvalidData, "a record of the worker channel is too long"));
+        }
+        let mut sealed = vec![0u8; nbytes as usize + TAG_BYTES];
+        self.channel.read_bytes(&mut sealed)?;
+        let record = self.receive.cipher.decrypt(Nonce::from_slice(&self.receive.nonce()), Payload{ msg: &sealed, aad: &length })
+                                        .map_err(|_| Error::new(ErrorKind::InvalidData, "a record of the worker channel failed authentication"))?;
+        self.receive.record += 1;
+        self.received = record;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+/// A channel between a coordinator and one of its workers, encrypted and authenticated
+/// with the worker key of their party. The bytes written are sent on `flush`.
+pub struct SealedChannel<C> {
+    inner: Rc<RefCell<Sealed<C>>>,
+}
+
+impl<C: AbstractChannel> SealedChannel<C> {
+    /// Open the channel on the side of the coordinator.
+    pub fn coordinator(channel: C, key: Block) -> Result<SealedChannel<C>, Error> {
+        SealedChannel::handshake(channel, key, true)
+    }
+
+    /// Open the channel on the side of the worker.
+    pub fn worker(channel: C, key: Block) -> Result<SealedChannel<C>, Error> {
+        SealedChannel::handshake(channel, key, false)
+    }
+
+    // Both sides send a fresh nonce, and the keys of the session are derived from the worker
+    // key, salted with both nonces.
+    fn handshake(mut channel: C, key: Block, coordinator: bool) -> Result<SealedChannel<C>, Error> {
+        let nonce = AesRng::new().gen::<u128>();
+        channel.write_bytes(&nonce.to_le_bytes())?;
+        channel.flush()?;
+        let mut other = [0u8; 16];
+        channel.read_bytes(&mut other)?;
+        let other = u128::from_le_bytes(other);
+        let (nonce_coordinator, nonce_worker) = if coordinator { (nonce, other) } else { (other, nonce) };
+
+        let mut salt = [0u8; 32];
+        salt[..16].copy_from_slice(&nonce_coordinator.to_le_bytes());
+        salt[16..].copy_from_slice(&nonce_worker.to_le_bytes());
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), &u128::from(key).to_le_bytes());
+        let to_worker = Keys::derive(&hkdf, TO_WORKER);
+        let to_coordinator = Keys::derive(&hkdf, TO_COORDINATOR);
+        let (send, receive) = if coordinator { (to_worker, to_coordinator) } else { (to_coordinator, to_worker) };
+        Ok(SealedChannel{
+            inner: Rc::new(RefCell::new(Sealed{
+                channel,
+                send,
+                receive,
+                written: Vec::new(),
+                received: Vec::new(),
+                position: 0,
+            })),
+        })
+    }
+}
+
+impl<C: AbstractChannel> AbstractChannel for SealedChannel<C> {
+    fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
+        let mut inner = self.inner.borrow_mut();
+        let mut filled = 0;
+        while filled < bytes.len() {
+            if inner.position == inner.received.len() {
+                inner.open()?;
+            }
+            let n = (bytes.len() - filled).min(inner.received.len() - inner.position);
+            bytes[filled..filled + n].copy_from_slice(&inner.received[inner.position..inner.position + n]);
+            inner.position += n;
+            filled += n;
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.inner.borrow_mut().written.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        let mut inner = self.inner.borrow_mut();
+        let written = std::mem::take(&mut inner.written);
+        for record in written.chunks(MAX_RECORD) {
+            inner.seal(record)?;
+        }
+        inner.channel.flush()
+    }
+
+    fn clone(&self) -> Self {
+        SealedChannel{
+            inner: Rc::clone(&self.inner),
+        }
+    }
+}
+
+/// Send the communication of a worker with the other party, in kilobits read and written.
+pub fn send_communication<C: AbstractChannel>(channel: &mut C, read: f64, written: f64) -> Result<(), Error> {
+    channel.write_u64(read.to_bits())?;
+    channel.write_u64(written.to_bits())?;
+    channel.flush()
+}
+
+/// Receive the communication sent by `send_communication`.
+pub fn receive_communication<C: AbstractChannel>(channel: &mut C) -> Result<(f64, f64), Error> {
+    let read = f64::from_bits(channel.read_u64()?);
+    let written = f64::from_bits(channel.read_u64()?);
+    Ok((read, written))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    // One end of an in-memory connection. Every byte written is logged, and the byte
+    // written at `corrupt`, if any, is flipped on its way.
+    struct Pipe {
+        incoming: Arc<Mutex<VecDeque<u8>>>,
+        outgoing: Arc<Mutex<VecDeque<u8>>>,
+        log: Arc<Mutex<Vec<u8>>>,
+        corrupt: Option<usize>,
+    }
+
+    impl AbstractChannel for Pipe {
+        fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
+            for x in bytes.iter_mut() {
+                *x = loop {
+                    if let Some(x) = self.incoming.lock().unwrap().pop_front() {
+                        break x;
+                    }
+                    thread::yield_now();
+                };
+            }
+            Ok(())
+        }
+
+        fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+            let mut log = self.log.lock().unwrap();
+            for x in bytes {
+                let flip = (self.corrupt == Some(log.len())) as u8;
+                self.outgoing.lock().unwrap().push_back(x ^ flip);
+                log.push(*x);
+            }
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn clone(&self) -> Self {
+            Pipe{
+                incoming: Arc::clone(&self.incoming),
+                outgoing: Arc::clone(&self.outgoing),
+                log: Arc::clone(&self.log),
+                corrupt: self.corrupt,
+            }
+        }
+    }
+
+    // Send `message` from a coordinator to a worker and return what the worker read,
+    // along with the bytes the coordinator sent.
+    fn send(message: &[u8], coordinator_key: u128, worker_key: u128, corrupt: Option<usize>)
+        -> (Result<Vec<u8>, Error>, Vec<u8>) {
+        let (a, b) = (Arc::new(Mutex::new(VecDeque::new())), Arc::new(Mutex::new(VecDeque::new())));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let coordinator = Pipe{ incoming: Arc::clone(&a), outgoing: Arc::clone(&b), log: Arc::clone(&log), corrupt };
+        let worker = Pipe{ incoming: b, outgoing: a, log: Arc::new(Mutex::new(Vec::new())), corrupt: None };
+
+        let length = message.len();
+        let worker = thread::spawn(move || {
+            let mut channel = SealedChannel::worker(worker, Block::from(worker_key))?;
+            let mut received = vec![0u8; length];
+            channel.read_bytes(&mut received)?;
+            Ok(received)
+        });
+        let mut channel = SealedChannel::coordinator(coordinator, Block::from(coordinator_key)).unwrap();
+        channel.write_bytes(message).unwrap();
+        channel.flush().unwrap();
+        let received = worker.join().unwrap();
+        let sent = log.lock().unwrap().clone();
+        (received, sent)
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let message = b"the states of a batch, over several blocks of the keystream";
+        let (received, sent) = send(message, 42, 42, None);
+        assert_eq!(received.unwrap(), message.to_vec());
+        // The nonce, the length of the record, the record and its tag
+        assert_eq!(sent.len(), 16 + 8 + message.len() + TAG_BYTES);
+        assert!(!sent.windows(6).any(|window| window == b"states"));
+    }
+
+    #[test]
+    fn long_writes_are_split_into_records() {
+        let message = (0..MAX_RECORD + 100).map(|i| i as u8).collect::<Vec<u8>>();
+        let (received, sent) = send(&message, 42, 42, None);
+        assert_eq!(received.unwrap(), message);
+        assert_eq!(sent.len(), 16 + 2 * (8 + TAG_BYTES) + message.len());
+    }
+
+    #[test]
+    fn oversized_records_are_rejected() {
+        // The highest byte of the length of the record is flipped
+        let (received, _) = send(b"the deltas of the server", 42, 42, Some(16 + 7));
+        assert!(received.unwrap_err().to_string().contains("too long"));
+    }
+
+    #[test]
+    fn tampered_records_are_rejected() {
+        let (received, _) = send(b"the deltas of the server", 42, 42, Some(16 + 8 + 3));
+        assert!(received.is_err());
+    }
+
+    #[test]
+    fn other_keys_are_rejected() {
+        let (received, _) = send(b"the deltas of the server", 42, 43, None);
+        assert!(received.is_err());
+    }
+}
@@ -0,0 +1,287 @@
+// Automatic choice of the number of threads and of the size of the megabins.
+//
+// When `nthread` or `megasize` is set to `auto`, they are picked from the set sizes, the cores
+// of both parties and their memory budget. Before the first pass, the client sends its
+// resources and its megabin setting to the server, which answers with its own. Both parties
+// then run the same choice on the same inputs, so that they agree on its result, and log it
+// along with the reasoning.
+//
+// The memory model is a rough estimate: the client's cuckoo table has about 1.27 bins per item,
+// every server item is hashed into 3 bins, and every server item of a bin costs the wires of
+// its payload bundle and of its equality test while the megabin is computed. Every thread holds
+// the megabins of one batch at a time.
+use scuttlebutt::AbstractChannel;
+use std::{
+    io::Error,
+    thread,
+};
+
+const CUCKOO_EXPANSION: f64 = 1.27;
+const NHASHES: usize = 3;
+const EQUALITY_WIRES: usize = 64;
+const WIRE_BYTES: usize = 16;
+/// Number of batches every thread should pull, so that the threads balance their load
+/// through the queue.
+const BATCHES_PER_THREAD: usize = 4;
+
+/// A setting of the configuration that is either fixed or chosen automatically.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Setting {
+    Auto,
+    Fixed(usize),
+}
+
+impl Setting {
+    pub fn parse(value: &str, key: &str) -> Setting {
+        if value == "auto" {
+            return Setting::Auto;
+        }
+        let value = value.parse::<usize>()
+                         .unwrap_or_else(|_| panic!("{} should be a positive integer or auto", key));
+        assert!(value > 0, "{} should be a positive integer or auto", key);
+        Setting::Fixed(value)
+    }
+
+    // A fixed setting is sent as its value, and auto as 0
+    fn send<C: AbstractChannel>(&self, channel: &mut C) -> Result<(), Error> {
+        match self {
+            Setting::Auto => channel.write_usize(0),
+            Setting::Fixed(value) => channel.write_usize(*value),
+        }
+    }
+
+    fn receive<C: AbstractChannel>(channel: &mut C) -> Result<Setting, Error> {
+        match channel.read_usize()? {
+            0 => Ok(Setting::Auto),
+            value => Ok(Setting::Fixed(value)),
+        }
+    }
+}
+
+/// What a party brings to the computation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resources {
+    pub nthread: Setting,
+    pub ncores: usize,
+    /// Memory the threads may use, in bytes.
+    pub memory_budget: usize,
+    /// Number of records of the party: the padded set of the client, and the agreed
+    /// maximum set size for the server, which does not reveal its own.
+    pub set_size: usize,
+}
+
+impl Resources {
+    /// The resources of this machine.
+    pub fn local(nthread: Setting, memory_budget: usize, set_size: usize) -> Resources {
+        Resources{
+            nthread,
+            ncores: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            memory_budget,
+            set_size,
+        }
+    }
+
+    fn send<C: AbstractChannel>(&self, channel: &mut C) -> Result<(), Error> {
+        self.nthread.send(channel)?;
+        channel.write_usize(self.ncores)?;
+        channel.write_usize(self.memory_budget)?;
+        channel.write_usize(self.set_size)
+    }
+
+    fn receive<C: AbstractChannel>(channel: &mut C) -> Result<Resources, Error> {
+        Ok(Resources{
+            nthread: Setting::receive(channel)?,
+            ncores: channel.read_usize()?,
+            memory_budget: channel.read_usize()?,
+            set_size: channel.read_usize()?,
+        })
+    }
+}
+
+/// The number of threads and the size of the megabins the parties agreed on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuning {
+    pub nthread: usize,
+    pub megasize: usize,
+    /// How the values were chosen, in order.
+    pub reasons: Vec<String>,
+}
+
+impl Tuning {
+    pub fn log(&self, party: &str) {
+        for reason in self.reasons.iter() {
+            println!("{} :: Tuning :: {}", party, reason);
+        }
+        println!("{} :: Tuning :: nthread {}, megasize {}", party, self.nthread, self.megasize);
+    }
+}
+
+fn thread_cap(party: &str, resources: &Resources, reasons: &mut Vec<String>) -> usize {
+    match resources.nthread {
+        Setting::Fixed(nthread) => {
+            reasons.push(format!("the {} runs at most {} threads, as configured", party, nthread));
+            nthread
+        }
+        Setting::Auto => {
+            reasons.push(format!("the {} has {} cores", party, resources.ncores));
+            resources.ncores
+        }
+    }
+}
+
+/// Choose the number of threads and the size of the megabins from the resources of both parties
+/// and the client's megabin setting. `batch_size` is the number of megabins per batch.
+pub fn tune(client: &Resources, server: &Resources, megasize: Setting,
+            payload_size: usize, batch_size: usize) -> Tuning {
+    let mut reasons = Vec::new();
+
+    let nbins = ((client.set_size as f64 * CUCKOO_EXPANSION).ceil() as usize).max(1);
+    let nprimes = fancy_garbling::util::primes_with_width(payload_size as u32).len();
+    let bin_bytes = WIRE_BYTES * (NHASHES * server.set_size / nbins + 1) * (nprimes + EQUALITY_WIRES);
+    let memory_budget = client.memory_budget.min(server.memory_budget);
+    reasons.push(format!("about {} bins of {} kB each, within a budget of {} MB",
+                         nbins, bin_bytes / 1000, memory_budget / 1_000_000));
+
+    let mut nthread = thread_cap("client", client, &mut reasons).min(thread_cap("server", server, &mut reasons));
+
+    let megasize = match megasize {
+        Setting::Fixed(megasize) => {
+            reasons.push(format!("megabins of {} bins, as configured by the client", megasize));
+            megasize
+        }
+        Setting::Auto => {
+            let by_memory = memory_budget / (nthread * batch_size * bin_bytes);
+            let by_balance = nbins / (nthread * batch_size * BATCHES_PER_THREAD);
+            reasons.push(format!("megabins of at most {} bins fit in memory, and of at most {} bins leave {} batches \
+                                  to every thread", by_memory, by_balance, BATCHES_PER_THREAD));
+            by_memory.min(by_balance).max(1)
+        }
+    };
+
+    let nmegabins = (nbins + megasize - 1) / megasize;
+    let nbatches = (nmegabins + batch_size - 1) / batch_size;
+    reasons.push(format!("about {} megabins in {} batches", nmegabins, nbatches));
+    if nthread > nbatches {
+        reasons.push(format!("there are no more than {} batches to share among the threads", nbatches));
+        nthread = nbatches;
+    }
+
+    let batch_bytes = batch_size * megasize * bin_bytes;
+    let by_memory = (memory_budget / batch_bytes).max(1);
+    if nthread > by_memory {
+        if client.nthread == Setting::Auto || server.nthread == Setting::Auto {
+            reasons.push(format!("only {} threads fit in memory with megabins of {} bins", by_memory, megasize));
+            nthread = by_memory;
+        }else{
+            reasons.push(format!("warning, {} threads may exceed the memory budget with megabins of {} bins",
+                                 nthread, megasize));
+        }
+    }
+
+    Tuning{
+        nthread,
+        megasize,
+        reasons,
+    }
+}
+
+/// Send the client's resources and megabin setting to the server, receive its resources
+/// and choose the tuning both parties agree on.
+pub fn agree_client<C: AbstractChannel>(channel: &mut C, resources: &Resources, megasize: Setting,
+                                        payload_size: usize, batch_size: usize) -> Result<Tuning, Error> {
+    resources.send(channel)?;
+    megasize.send(channel)?;
+    channel.flush()?;
+    let server = Resources::receive(channel)?;
+    Ok(tune(resources, &server, megasize, payload_size, batch_size))
+}
+
+/// Receive the client's resources and megabin setting, send the server's resources
+/// and choose the tuning both parties agree on.
+pub fn agree_server<C: AbstractChannel>(channel: &mut C, resources: &Resources,
+                                        payload_size: usize, batch_size: usize) -> Result<Tuning, Error> {
+    let client = Resources::receive(channel)?;
+    let megasize = Setting::receive(channel)?;
+    resources.send(channel)?;
+    channel.flush()?;
+    Ok(tune(&client, resources, megasize, payload_size, batch_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1_000_000;
+
+    fn resources(nthread: Setting, ncores: usize, memory_budget: usize, set_size: usize) -> Resources {
+        Resources{ nthread, ncores, memory_budget, set_size }
+    }
+
+    #[test]
+    fn fixed_threads_and_megabins() {
+        let client = resources(Setting::Fixed(4), 16, 4096 * MB, 10_000);
+        let server = resources(Setting::Fixed(8), 16, 4096 * MB, 10_000);
+        let tuning = tune(&client, &server, Setting::Fixed(100), 64, 1);
+        assert_eq!(tuning.nthread, 4);
+        assert_eq!(tuning.megasize, 100);
+    }
+
+    #[test]
+    fn threads_capped_by_the_batches_of_the_megabins() {
+        // 12700 bins in megabins of 1000 bins make 13 megabins, in 7 batches of 2
+        let client = resources(Setting::Fixed(32), 32, 4096 * MB, 10_000);
+        let server = resources(Setting::Auto, 32, 4096 * MB, 10_000);
+        let tuning = tune(&client, &server, Setting::Fixed(1000), 64, 2);
+        assert_eq!(tuning.megasize, 1000);
+        assert_eq!(tuning.nthread, 7);
+    }
+
+    #[test]
+    fn auto_threads_fixed_megabins() {
+        let client = resources(Setting::Auto, 6, 4096 * MB, 100_000);
+        let server = resources(Setting::Auto, 12, 4096 * MB, 100_000);
+        let tuning = tune(&client, &server, Setting::Fixed(100), 64, 1);
+        assert_eq!(tuning.nthread, 6);
+        assert_eq!(tuning.megasize, 100);
+    }
+
+    #[test]
+    fn fixed_threads_auto_megabins() {
+        let client = resources(Setting::Fixed(4), 16, 4096 * MB, 100_000);
+        let server = resources(Setting::Fixed(4), 16, 4096 * MB, 100_000);
+        let tuning = tune(&client, &server, Setting::Auto, 64, 1);
+        assert_eq!(tuning.nthread, 4);
+        // Every thread pulls BATCHES_PER_THREAD batches of the 127000 bins
+        assert_eq!(tuning.megasize, 127_000 / (4 * BATCHES_PER_THREAD));
+    }
+
+    #[test]
+    fn auto_threads_and_megabins() {
+        let client = resources(Setting::Auto, 8, 4096 * MB, 100_000);
+        let server = resources(Setting::Auto, 4, 4096 * MB, 100_000);
+        let tuning = tune(&client, &server, Setting::Auto, 64, 2);
+        assert_eq!(tuning.nthread, 4);
+        assert_eq!(tuning.megasize, 127_000 / (4 * 2 * BATCHES_PER_THREAD));
+        let nbatches = (127_000 + 2 * tuning.megasize - 1) / (2 * tuning.megasize);
+        assert!(nbatches >= tuning.nthread * BATCHES_PER_THREAD);
+    }
+
+    #[test]
+    fn auto_threads_capped_by_memory() {
+        // A single bin takes the whole budget of the smaller party
+        let client = resources(Setting::Auto, 8, 1000 * MB, 1000);
+        let server = resources(Setting::Auto, 8, MB, 1_000_000);
+        let tuning = tune(&client, &server, Setting::Fixed(1000), 64, 1);
+        assert_eq!(tuning.nthread, 1);
+        assert!(tuning.reasons.iter().any(|reason| reason.starts_with("only 1 threads fit in memory")));
+    }
+
+    #[test]
+    fn fixed_threads_warned_about_memory() {
+        let client = resources(Setting::Fixed(8), 8, 1000 * MB, 1000);
+        let server = resources(Setting::Fixed(8), 8, MB, 1_000_000);
+        let tuning = tune(&client, &server, Setting::Fixed(100), 64, 1);
+        assert_eq!(tuning.nthread, 8);
+        assert!(tuning.reasons.iter().any(|reason| reason.starts_with("warning")));
+    }
+}